@@ -0,0 +1,59 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dft::{Operation, Plan};
+use lamper::spectrum::{Spectrum, Window};
+use std::f32::consts::TAU;
+
+const WINDOW: usize = 4096;
+
+// the per frame transform colproc used before Spectrum, new plan and f64 buffers every call
+fn dft_per_frame(data: Vec<f32>) -> Vec<f32> {
+    let plan = Plan::new(Operation::Forward, WINDOW);
+
+    let mut input = Vec::with_capacity(WINDOW);
+    for x in data {
+        input.push(x as f64);
+    }
+
+    dft::transform(&mut input, &plan);
+    let output = dft::unpack(&input);
+    let mut result = Vec::with_capacity(WINDOW);
+    for ref c in output {
+        result.push(c.norm() as f32);
+    }
+    result
+}
+
+fn frame() -> Vec<f32> {
+    (0..WINDOW)
+        .map(|i| (TAU * 440.0 * i as f32 / 44100.0).sin())
+        .collect()
+}
+
+fn bench(c: &mut Criterion) {
+    let data = frame();
+    let mut group = c.benchmark_group("spectrum");
+
+    group.bench_function("dft per frame", |b| {
+        b.iter(|| dft_per_frame(black_box(data.clone())))
+    });
+
+    let mut spectrum = Spectrum::new(WINDOW, Window::Rectangular);
+    group.bench_function("cached rectangular", |b| {
+        b.iter(|| spectrum.magnitude(black_box(&data)).len())
+    });
+
+    let mut spectrum = Spectrum::new(WINDOW, Window::Hann);
+    group.bench_function("cached hann", |b| {
+        b.iter(|| spectrum.magnitude(black_box(&data)).len())
+    });
+
+    let mut spectrum = Spectrum::new(WINDOW, Window::BlackmanHarris);
+    group.bench_function("cached blackman-harris db", |b| {
+        b.iter(|| spectrum.db(black_box(&data)).len())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
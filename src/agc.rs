@@ -0,0 +1,93 @@
+// automatic gain control, turns frame levels in dB into brightness
+
+// levels are in dB relative to a full scale sine
+#[derive(Debug, Clone, Copy)]
+pub struct AgcOpts {
+    // brightness, 0-1, a level sitting at the tracked reference maps to
+    pub target: f32,
+    // seconds for the reference to rise to a louder level
+    pub attack: f32,
+    // seconds for the reference to fall to a quieter level
+    pub release: f32,
+    // levels below this are treated as silence
+    pub gate: f32,
+    // dB below the reference that fade from target to off
+    pub range: f32,
+}
+
+impl Default for AgcOpts {
+    fn default() -> Self {
+        AgcOpts {
+            target: 0.8,
+            attack: 0.05,
+            release: 3.0,
+            gate: -70.0,
+            range: 30.0,
+        }
+    }
+}
+
+pub struct Agc {
+    opts: AgcOpts,
+    attack: f32,
+    release: f32,
+    reference: Option<f32>,
+}
+
+impl Agc {
+    // fps is the rate process is called at
+    pub fn new(opts: AgcOpts, fps: f32) -> Self {
+        let coeff = |secs: f32| {
+            if secs <= 0.0 {
+                1.0
+            } else {
+                1.0 - (-1.0 / (secs * fps)).exp()
+            }
+        };
+        Agc {
+            attack: coeff(opts.attack),
+            release: coeff(opts.release),
+            opts,
+            reference: None,
+        }
+    }
+
+    // brightness 0-100 for a frame level in dB
+    pub fn process(&mut self, level: f32) -> u8 {
+        // hold the reference while gated so silence doesn't drag it into the noise
+        if !level.is_finite() || level < self.opts.gate {
+            return 0;
+        }
+
+        let reference = match self.reference {
+            Some(reference) => {
+                let coeff = if level > reference {
+                    self.attack
+                } else {
+                    self.release
+                };
+                reference + (level - reference) * coeff
+            }
+            None => level,
+        };
+        self.reference = Some(reference);
+
+        let brightness = self.opts.target + (level - reference) / self.opts.range;
+        (brightness.clamp(0.0, 1.0) * 100.0).round() as u8
+    }
+
+    // level the output is currently normalized against, None until the first ungated frame
+    pub fn reference(&self) -> Option<f32> {
+        self.reference
+    }
+}
+
+// level of a frame of samples in dB relative to a full scale sine
+pub fn level(data: &[f32]) -> f32 {
+    if data.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let mean = data.iter().map(|x| x * x).sum::<f32>() / data.len() as f32;
+    // a full scale sine has a mean square of 0.5
+    10.0 * (2.0 * mean).log10()
+}
@@ -0,0 +1,287 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{
+    future,
+    stream::{self, Stream},
+};
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+
+use crate::{
+    protocol::{Request, Response, Scan},
+    udp::{Cmd, CmdErr, DeviceInfo, DiscoveryOptions, InitErr, State},
+};
+
+// async take on udp, every lamp shares the hub's socket and a single task reads the
+// replies and hands them to whoever is waiting, so any number of lamps can be driven
+// from one runtime without a thread each
+
+// where a devStatus reply goes
+type Waiter = oneshot::Sender<Result<State, CmdErr>>;
+
+// who is waiting for which replies
+#[derive(Debug, Default)]
+struct Router {
+    // devStatus replies by lamp address
+    status: Mutex<HashMap<Ipv4Addr, Vec<Waiter>>>,
+    // discover streams still running
+    scans: Mutex<Vec<mpsc::UnboundedSender<Scan>>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    socket: Arc<UdpSocket>,
+    router: Arc<Router>,
+    opts: DiscoveryOptions,
+    task: JoinHandle<()>,
+}
+
+// the bound reply port, cheap to clone, the reading task stops once the hub and every
+// lamp from it are dropped
+#[derive(Debug, Clone)]
+pub struct Hub {
+    shared: Arc<Shared>,
+}
+
+// same as udp::Lamp
+#[derive(Debug)]
+pub struct Lamp {
+    shared: Arc<Shared>,
+    pub addr: SocketAddrV4,
+    pub init: State,
+}
+
+// discover stream state
+struct Scanning {
+    shared: Arc<Shared>,
+    rx: mpsc::UnboundedReceiver<Scan>,
+    seen: HashSet<String>,
+    scans: usize,
+    deadline: Option<Instant>,
+    done: bool,
+}
+
+impl Router {
+    fn route(&self, ip: Ipv4Addr, buf: &[u8]) {
+        match Response::parse(buf) {
+            Ok(Response::DevStatus(status)) => {
+                for tx in self.waiting(ip) {
+                    let _ = tx.send(State::try_from(status.clone()));
+                }
+            }
+            Ok(Response::Scan(scan)) => self
+                .scans
+                .lock()
+                .unwrap()
+                .retain(|tx| tx.send(scan.clone()).is_ok()),
+            // garbage from a lamp that is being asked for its status is its answer
+            Err(err) => {
+                let err = err.to_string();
+                for tx in self.waiting(ip) {
+                    let _ = tx.send(Err(CmdErr::SerdeErr(err.clone())));
+                }
+            }
+        }
+    }
+
+    fn waiting(&self, ip: Ipv4Addr) -> Vec<Waiter> {
+        self.status.lock().unwrap().remove(&ip).unwrap_or_default()
+    }
+
+    // drop waiters that gave up
+    fn forget(&self, ip: Ipv4Addr) {
+        let mut status = self.status.lock().unwrap();
+        if let Some(waiting) = status.get_mut(&ip) {
+            waiting.retain(|tx| !tx.is_closed());
+            if waiting.is_empty() {
+                status.remove(&ip);
+            }
+        }
+    }
+}
+
+// read replies until aborted, errors are per packet on udp so they are skipped
+async fn read(socket: Arc<UdpSocket>, router: Arc<Router>) {
+    let mut buf = [0u8; 1024];
+    loop {
+        if let Ok((len, IpAddr::V4(ip))) = socket
+            .recv_from(&mut buf)
+            .await
+            .map(|(len, src)| (len, src.ip()))
+        {
+            router.route(ip, &buf[..len]);
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Shared {
+    async fn status(&self, addr: SocketAddrV4) -> Result<State, CmdErr> {
+        let ip = *addr.ip();
+        let (tx, rx) = oneshot::channel();
+        self.router
+            .status
+            .lock()
+            .unwrap()
+            .entry(ip)
+            .or_default()
+            .push(tx);
+        let res = match self.send(Request::DevStatus {}, addr).await {
+            Ok(_) => match time::timeout(self.opts.read_timeout, rx).await {
+                Ok(Ok(res)) => res,
+                // the reading task is gone
+                Ok(Err(_)) => Err(CmdErr::RecvErr(ErrorKind::BrokenPipe)),
+                Err(_) => Err(CmdErr::RecvErr(ErrorKind::TimedOut)),
+            },
+            Err(err) => Err(err),
+        };
+        if res.is_err() {
+            self.router.forget(ip);
+        }
+        res
+    }
+
+    async fn send(&self, req: Request, addr: SocketAddrV4) -> Result<(), CmdErr> {
+        self.socket.send_to(&req.to_vec()?, addr).await?;
+        Ok(())
+    }
+}
+
+impl Hub {
+    // bind the reply port, taken ports are an error like in udp
+    pub async fn bind(opts: DiscoveryOptions) -> Result<Hub, InitErr> {
+        let socket = opts.bind()?;
+        socket.set_nonblocking(true)?;
+        let socket = Arc::new(UdpSocket::from_std(socket)?);
+        socket.set_multicast_ttl_v4(opts.ttl)?;
+        let router = Arc::new(Router::default());
+        let task = tokio::spawn(read(Arc::clone(&socket), Arc::clone(&router)));
+        Ok(Hub {
+            shared: Arc::new(Shared {
+                socket,
+                router,
+                opts,
+                task,
+            }),
+        })
+    }
+
+    // every lamp answering a scan as it answers, ends after the scan window, scanning
+    // again up to opts.retries times while nothing answered
+    pub fn discover(&self) -> impl Stream<Item = Result<DeviceInfo, InitErr>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.shared.router.scans.lock().unwrap().push(tx);
+        let scanning = Scanning {
+            shared: Arc::clone(&self.shared),
+            rx,
+            seen: HashSet::new(),
+            scans: 0,
+            deadline: None,
+            done: false,
+        };
+        stream::unfold(scanning, |mut scanning| async move {
+            scanning.next().await.map(|item| (item, scanning))
+        })
+    }
+
+    // query a lamp at a known address, no scan
+    pub async fn connect(&self, ip: Ipv4Addr) -> Result<Lamp, InitErr> {
+        let addr = SocketAddrV4::new(ip, self.shared.opts.cmd_port);
+        let init = self.shared.status(addr).await?;
+        Ok(Lamp {
+            shared: Arc::clone(&self.shared),
+            addr,
+            init,
+        })
+    }
+
+    // connect to several lamps at once
+    pub async fn connect_all(&self, devices: &[DeviceInfo]) -> Result<Vec<Lamp>, InitErr> {
+        future::join_all(devices.iter().map(|info| self.connect(info.ip)))
+            .await
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Scanning {
+    async fn next(&mut self) -> Option<Result<DeviceInfo, InitErr>> {
+        loop {
+            if self.done {
+                return None;
+            }
+            let deadline = match self.deadline {
+                Some(deadline) => deadline,
+                None => {
+                    let opts = &self.shared.opts;
+                    let group = SocketAddrV4::new(opts.multicast, opts.scan_port);
+                    let timeout = opts.timeout;
+                    let req = Request::Scan {
+                        account_topic: "reserve".to_string(),
+                    };
+                    if let Err(err) = self.send(req, group).await {
+                        self.done = true;
+                        return Some(Err(err));
+                    }
+                    self.scans += 1;
+                    *self.deadline.insert(Instant::now() + timeout)
+                }
+            };
+            match time::timeout_at(deadline, self.rx.recv()).await {
+                Ok(Some(scan)) => {
+                    // ignore anything that isn't a valid scan response
+                    if let Ok(info) = DeviceInfo::try_from(scan) {
+                        if self.seen.insert(info.device.clone()) {
+                            return Some(Ok(info));
+                        }
+                    }
+                }
+                Ok(None) => return None,
+                Err(_) => {
+                    self.done = !self.seen.is_empty() || self.scans > self.shared.opts.retries;
+                    self.deadline = None;
+                }
+            }
+        }
+    }
+
+    async fn send(&self, req: Request, group: SocketAddrV4) -> Result<(), InitErr> {
+        let buf = req.to_vec()?;
+        self.shared
+            .socket
+            .send_to(&buf, group)
+            .await
+            .map_err(|err| InitErr::ScanErr(err.kind()))?;
+        Ok(())
+    }
+}
+
+impl Lamp {
+    pub async fn send_cmd(&self, cmd: Cmd) -> Result<(), CmdErr> {
+        self.shared.send(cmd.request()?, self.addr).await
+    }
+
+    // current state, RecvErr(TimedOut) if no reply within opts.read_timeout
+    pub async fn status(&self) -> Result<State, CmdErr> {
+        self.shared.status(self.addr).await
+    }
+
+    // check if still connected
+    pub async fn check(&self) -> Result<(), CmdErr> {
+        self.status().await.map(|_| ())
+    }
+}
@@ -1,19 +1,24 @@
-// todo:
-// default device/selection
-
 use libpulse_binding::{
     self,
+    callbacks::ListResult,
+    context::{self, introspect::SourceInfo, Context, FlagSet},
     error::PAErr,
+    mainloop::standard::{IterateResult, Mainloop},
+    operation::{self, Operation},
     sample::{Format, Spec},
     stream::Direction,
 };
 use libpulse_simple_binding::{self, Simple};
-use std::sync::{
-    mpsc::{SendError, Sender},
-    Arc, RwLock,
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        mpsc::{SendError, Sender},
+        Arc, RwLock,
+    },
 };
 
-use crate::{LampErr, WINDOW};
+use crate::{health::Status, Frames, LampErr, RATE};
 
 impl From<PAErr> for LampErr {
     fn from(_: PAErr) -> Self {
@@ -27,38 +32,206 @@ impl<T> From<SendError<T>> for LampErr {
     }
 }
 
-pub fn start(tx: Sender<Vec<f32>>, conn: Arc<RwLock<bool>>) -> Result<(), LampErr> {
-    // interface specs
-    let spec = Spec {
-        format: Format::FLOAT32NE,
-        channels: 1,
-        rate: 44100,
-    };
-    assert!(spec.is_valid());
-
-    // create libpulse-simple interface
-    let s = Simple::new(
-        None,
-        "lamper",
-        Direction::Record,
-        Some("alsa_output.usb-BurrBrown_from_Texas_Instruments_USB_AUDIO_CODEC-00.analog-stereo.monitor"),
-        "Lamper",
-        &spec,
-        None,
-        None
-    )?;
-
-    // send data to colproc thread
+// pulse audio source, monitors are flagged so they can be listed separately
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub description: String,
+    pub monitor: bool,
+    pub default: bool,
+}
+
+// list every source on the pulse audio server, the default sink's monitor is marked as default
+pub fn sources() -> Result<Vec<Source>, LampErr> {
+    let mut mainloop = Mainloop::new().ok_or(LampErr::PAErr)?;
+    let mut context = Context::new(&mainloop, "lamper").ok_or(LampErr::PAErr)?;
+    context.connect(None, FlagSet::NOFLAGS, None)?;
+
+    // wait for the context to be ready
     loop {
-        if !*conn.read().unwrap() {
-            return Ok(());
+        iterate(&mut mainloop)?;
+        match context.get_state() {
+            context::State::Ready => break,
+            context::State::Failed | context::State::Terminated => return Err(LampErr::PAErr),
+            _ => {}
+        }
+    }
+
+    let introspector = context.introspect();
+
+    // default sink name
+    let default_sink = Rc::new(RefCell::new(None));
+    let sink = Rc::clone(&default_sink);
+    let op = introspector.get_server_info(move |info| {
+        *sink.borrow_mut() = info.default_sink_name.as_ref().map(|name| name.to_string());
+    });
+    wait(&mut mainloop, &op)?;
+
+    // every source, monitors included
+    let list = Rc::new(RefCell::new(Vec::new()));
+    let failed = Rc::new(RefCell::new(false));
+    let items = Rc::clone(&list);
+    let fail = Rc::clone(&failed);
+    let default = default_sink.borrow().clone();
+    let op = introspector.get_source_info_list(move |res| match res {
+        ListResult::Item(info) => {
+            items.borrow_mut().push(source(info, default.as_deref()));
         }
-        let mut data = Vec::with_capacity(WINDOW);
-        for _ in 0..WINDOW {
-            data.push(0.0)
+        ListResult::End => {}
+        ListResult::Error => *fail.borrow_mut() = true,
+    });
+    wait(&mut mainloop, &op)?;
+
+    context.disconnect();
+    if *failed.borrow() {
+        return Err(LampErr::PAErr);
+    }
+
+    let sources = list.borrow().clone();
+    Ok(sources)
+}
+
+// monitor of the default sink, if there is one
+pub fn default_monitor() -> Result<Option<Source>, LampErr> {
+    Ok(sources()?.into_iter().find(|source| source.default))
+}
+
+fn source(info: &SourceInfo, default_sink: Option<&str>) -> Source {
+    let name = info.name.as_deref().unwrap_or_default().to_string();
+    let description = info.description.as_deref().unwrap_or_default().to_string();
+    let sink = info.monitor_of_sink_name.as_deref();
+    Source {
+        name,
+        description,
+        monitor: info.monitor_of_sink.is_some(),
+        default: sink.is_some() && sink == default_sink,
+    }
+}
+
+// run the mainloop once, blocking until something happens
+fn iterate(mainloop: &mut Mainloop) -> Result<(), LampErr> {
+    match mainloop.iterate(true) {
+        IterateResult::Success(_) => Ok(()),
+        IterateResult::Quit(_) => Err(LampErr::PAErr),
+        IterateResult::Err(err) => Err(err.into()),
+    }
+}
+
+// run the mainloop until the operation completes
+fn wait<T: ?Sized>(mainloop: &mut Mainloop, op: &Operation<T>) -> Result<(), LampErr> {
+    loop {
+        match op.get_state() {
+            operation::State::Running => iterate(mainloop)?,
+            operation::State::Done => return Ok(()),
+            operation::State::Cancelled => return Err(LampErr::PAErr),
         }
+    }
+}
+
+// anything that can produce frames of mono f32 samples at RATE
+pub trait AudioSource {
+    // next len samples, None once the source is exhausted
+    fn frame(&mut self, len: usize) -> Result<Option<Vec<f32>>, LampErr>;
+}
 
-        s.read(&mut data)?;
-        tx.send(data)?;
+// live capture from a pulse audio source
+pub struct Pulse {
+    simple: Simple,
+}
+
+impl Pulse {
+    // source is the pulse audio source name, None lets the server pick
+    pub fn new(source: Option<&str>) -> Result<Self, LampErr> {
+        // interface specs
+        let spec = Spec {
+            format: Format::FLOAT32NE,
+            channels: 1,
+            rate: RATE,
+        };
+        assert!(spec.is_valid());
+
+        // create libpulse-simple interface
+        let simple = Simple::new(
+            None,
+            "lamper",
+            Direction::Record,
+            source,
+            "Lamper",
+            &spec,
+            None,
+            None,
+        )?;
+
+        Ok(Pulse { simple })
+    }
+}
+
+impl AudioSource for Pulse {
+    fn frame(&mut self, len: usize) -> Result<Option<Vec<f32>>, LampErr> {
+        let mut data = vec![0.0; len];
+        self.simple.read(&mut data)?;
+        Ok(Some(data))
+    }
+}
+
+// holds the latest len samples so windows can overlap
+pub struct Ring {
+    buf: Vec<f32>,
+    pos: usize,
+    filled: usize,
+}
+
+impl Ring {
+    pub fn new(len: usize) -> Self {
+        Ring {
+            buf: vec![0.0; len],
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    pub fn push(&mut self, data: &[f32]) {
+        for x in data {
+            self.buf[self.pos] = *x;
+            self.pos = (self.pos + 1) % self.buf.len();
+        }
+        self.filled = (self.filled + data.len()).min(self.buf.len());
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.filled == self.buf.len()
+    }
+
+    // contents oldest sample first
+    pub fn window(&self) -> Vec<f32> {
+        let mut out = Vec::with_capacity(self.buf.len());
+        out.extend_from_slice(&self.buf[self.pos..]);
+        out.extend_from_slice(&self.buf[..self.pos]);
+        out
+    }
+}
+
+// read hop samples at a time from any audio source and send the latest window
+// to the colproc thread
+pub fn start<S: AudioSource>(
+    tx: Sender<Vec<f32>>,
+    status: Arc<RwLock<Status>>,
+    mut source: S,
+    frames: Frames,
+) -> Result<(), LampErr> {
+    let mut ring = Ring::new(frames.window);
+    loop {
+        if !status.read().unwrap().running() {
+            return Ok(());
+        }
+        match source.frame(frames.hop)? {
+            Some(data) => {
+                ring.push(&data);
+                if ring.is_full() {
+                    tx.send(ring.window())?;
+                }
+            }
+            None => return Ok(()),
+        }
     }
 }
@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+
+// seconds of flux history used for the adaptive threshold
+const HISTORY: f32 = 4.0;
+// standard deviations above the mean flux needed for an onset
+const SENSITIVITY: f32 = 1.5;
+// minimum seconds between onsets
+const MIN_GAP: f32 = 0.15;
+
+// spectral flux onset detector with an adaptive threshold
+pub struct Onset {
+    prev: Vec<f32>,
+    history: VecDeque<f32>,
+    len: usize,
+    since: u32,
+    gap: u32,
+    flux: f32,
+}
+
+impl Onset {
+    // fps is the rate detect is called at
+    pub fn new(fps: f32) -> Self {
+        let len = (fps * HISTORY).ceil() as usize;
+        let gap = (fps * MIN_GAP).ceil() as u32;
+        Onset {
+            prev: Vec::new(),
+            history: VecDeque::with_capacity(len),
+            len,
+            since: gap,
+            gap,
+            flux: 0.0,
+        }
+    }
+
+    // feed one magnitude spectrum, returns true if the frame is an onset
+    pub fn detect(&mut self, half: &[f32]) -> bool {
+        let flux = if self.prev.len() == half.len() {
+            half.iter()
+                .zip(self.prev.iter())
+                .map(|(cur, prev)| (cur - prev).max(0.0))
+                .sum()
+        } else {
+            0.0
+        };
+        self.prev.clear();
+        self.prev.extend_from_slice(half);
+        self.flux = flux;
+
+        let threshold = self.threshold();
+        let onset = self.history.len() == self.len && flux > threshold && self.since >= self.gap;
+
+        if self.history.len() == self.len {
+            self.history.pop_front();
+        }
+        self.history.push_back(flux);
+
+        if onset {
+            self.since = 0;
+        } else {
+            self.since = self.since.saturating_add(1);
+        }
+        onset
+    }
+
+    // flux of the last frame, the onset envelope
+    pub fn flux(&self) -> f32 {
+        self.flux
+    }
+
+    // mean plus SENSITIVITY standard deviations of recent flux
+    fn threshold(&self) -> f32 {
+        if self.history.is_empty() {
+            return f32::MAX;
+        }
+        let len = self.history.len() as f32;
+        let mean = self.history.iter().sum::<f32>() / len;
+        let var = self.history.iter().map(|f| (f - mean).powi(2)).sum::<f32>() / len;
+        mean + SENSITIVITY * var.sqrt()
+    }
+}
+
+// tempo range searched, in bpm
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 180.0;
+// seconds of onset envelope kept for the autocorrelation
+const ENVELOPE: f32 = 8.0;
+// how far the beat phase is pulled towards the envelope over one beat, below 2 so the
+// phase always moves forward
+const PHASE_GAIN: f32 = 0.5;
+// autocorrelation peak relative to the zero lag needed to trust the tempo
+const CONFIDENCE: f32 = 0.1;
+// tempo prior, lags are weighted by a log gaussian around PRIOR_BPM to avoid octave errors
+const PRIOR_BPM: f32 = 120.0;
+const PRIOR_OCTAVES: f32 = 1.0;
+
+// tempo estimator, autocorrelation of the onset envelope for bpm and a phase
+// accumulator pulled towards the envelope for beat timing
+pub struct Tempo {
+    fps: f32,
+    envelope: VecDeque<f32>,
+    len: usize,
+    period: Option<f32>,
+    phase: f32,
+}
+
+impl Tempo {
+    // fps is the rate update is called at
+    pub fn new(fps: f32) -> Self {
+        let len = (fps * ENVELOPE).ceil() as usize;
+        Tempo {
+            fps,
+            envelope: VecDeque::with_capacity(len),
+            len,
+            period: None,
+            phase: 0.0,
+        }
+    }
+
+    // feed one onset envelope value, returns true if a beat lands on this frame
+    pub fn update(&mut self, flux: f32) -> bool {
+        if self.envelope.len() == self.len {
+            self.envelope.pop_front();
+        }
+        self.envelope.push_back(flux);
+        if self.envelope.len() < self.len {
+            return false;
+        }
+
+        let env: Vec<f32> = {
+            let mean = self.envelope.iter().sum::<f32>() / self.len as f32;
+            self.envelope.iter().map(|f| f - mean).collect()
+        };
+        self.period = period(&env, self.fps);
+
+        let period = match self.period {
+            Some(period) => period,
+            None => return false,
+        };
+
+        // pull the phase towards the offset that best lines up with past onsets
+        // the pull is spread over the beat and folded into the step, so a correction
+        // across the end of the beat can't skip or repeat it
+        let prev = self.phase;
+        let target = offset(&env, period) / period;
+        let diff = (target - self.phase + 0.5).rem_euclid(1.0) - 0.5;
+        self.phase = (self.phase + (1.0 + diff * PHASE_GAIN) / period).rem_euclid(1.0);
+        // wrapped past the end of the beat
+        self.phase < prev
+    }
+
+    // current tempo, None until the envelope is full and has a clear period
+    pub fn bpm(&self) -> Option<f32> {
+        self.period.map(|period| 60.0 * self.fps / period)
+    }
+
+    // position within the current beat, 0 on the beat and approaching 1 before the next
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+}
+
+// beat period in frames from the weighted autocorrelation peak within the bpm range
+// onsets land on whole frames, so a period between two lags spreads its correlation
+// over both and each lag is scored together with its neighbours
+fn period(env: &[f32], fps: f32) -> Option<f32> {
+    let min_lag = ((60.0 * fps / MAX_BPM).floor() as usize).max(2);
+    let max_lag = (60.0 * fps / MIN_BPM).ceil() as usize;
+    if max_lag + 1 >= env.len() || min_lag >= max_lag {
+        return None;
+    }
+
+    let corr: Vec<f32> = (0..=max_lag + 1)
+        .map(|lag| {
+            let sum: f32 = env
+                .iter()
+                .skip(lag)
+                .zip(env.iter())
+                .map(|(a, b)| a * b)
+                .sum();
+            sum.max(0.0)
+        })
+        .collect();
+    if corr[0] <= 0.0 {
+        return None;
+    }
+    let weight = |period: f32| -> f32 {
+        let octaves = (60.0 * fps / period / PRIOR_BPM).log2() / PRIOR_OCTAVES;
+        (-0.5 * octaves * octaves).exp()
+    };
+
+    let mut best = (0, f32::MIN);
+    for lag in min_lag..=max_lag {
+        let score = (corr[lag - 1] + corr[lag] + corr[lag + 1]) * weight(lag as f32);
+        if score > best.1 {
+            best = (lag, score);
+        }
+    }
+
+    let lag = best.0;
+    let mass = corr[lag - 1] + corr[lag] + corr[lag + 1];
+    if mass / corr[0] < CONFIDENCE {
+        return None;
+    }
+    // centroid of the neighbourhood for a fractional period
+    let centroid = ((lag - 1) as f32 * corr[lag - 1]
+        + lag as f32 * corr[lag]
+        + (lag + 1) as f32 * corr[lag + 1])
+        / mass;
+    Some(centroid)
+}
+
+// frames since the last beat, the offset whose comb over the envelope scores highest
+fn offset(env: &[f32], period: f32) -> f32 {
+    let last = env.len() - 1;
+    let mut best = (0, f32::MIN);
+    for offset in 0..period.round().max(1.0) as usize {
+        let mut score = 0.0;
+        let mut k = 0.0;
+        while let Some(i) = last.checked_sub(offset + (k * period).round() as usize) {
+            score += env[i];
+            k += 1.0;
+        }
+        if score > best.1 {
+            best = (offset, score);
+        }
+    }
+    best.0 as f32
+}
@@ -0,0 +1,80 @@
+// maps the 0-100 brightness coming out of colproc to what is sent to a lamp
+
+// shape of the mapping between min and max
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    // level raised to the power, above 1 keeps quiet parts dim and makes peaks stand out
+    Gamma(f32),
+    // lifts quiet parts, loud parts are compressed together
+    Log,
+}
+
+impl Curve {
+    pub const GAMMA: f32 = 2.2;
+
+    // linear, log, gamma or gamma=G
+    pub fn parse(val: &str) -> Option<Curve> {
+        match val {
+            "linear" => Some(Curve::Linear),
+            "log" => Some(Curve::Log),
+            "gamma" => Some(Curve::Gamma(Curve::GAMMA)),
+            _ => {
+                let gamma = val.strip_prefix("gamma=")?.parse::<f32>().ok()?;
+                if gamma.is_finite() && gamma > 0.0 {
+                    Some(Curve::Gamma(gamma))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // x in 0-1 to 0-1
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            Curve::Linear => x,
+            Curve::Gamma(gamma) => x.powf(*gamma),
+            Curve::Log => (1.0 + 9.0 * x).log10(),
+        }
+    }
+}
+
+// levels above 0 are curved onto min-max, nothing goes below floor so the lamp stays
+// lit through quiet passages, min and floor above max are held at max
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BrightMap {
+    pub min: u8,
+    pub max: u8,
+    pub floor: u8,
+    pub curve: Curve,
+}
+
+impl BrightMap {
+    // brightness to send for a level, both 0-100
+    pub fn map(&self, level: u8) -> u8 {
+        let max = self.max.min(100);
+        let floor = self.floor.min(max);
+        if level == 0 {
+            return floor;
+        }
+        let min = self.min.min(max) as f32;
+        let x = self
+            .curve
+            .apply(level.min(100) as f32 / 100.0)
+            .clamp(0.0, 1.0);
+        let val = (min + (max as f32 - min) * x).round() as u8;
+        val.clamp(floor, max)
+    }
+}
+
+impl Default for BrightMap {
+    fn default() -> Self {
+        BrightMap {
+            min: 0,
+            max: 100,
+            floor: 0,
+            curve: Curve::Linear,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::udp::DeviceInfo;
+
+// lamps seen by earlier scans keyed by mac, so a lamp asked for by mac can still be
+// reached when multicast is blocked, and is found again after its address changes
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LampCache {
+    lamps: HashMap<String, DeviceInfo>,
+}
+
+impl LampCache {
+    pub fn new() -> Self {
+        LampCache::default()
+    }
+
+    // a missing or unreadable cache is just empty, it fills up again on the next scan
+    pub fn load(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|buf| serde_json::from_slice(&buf).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    // remember a scanned lamp, replacing whatever address it had before
+    pub fn insert(&mut self, info: &DeviceInfo) {
+        if !info.mac.is_empty() {
+            self.lamps.insert(info.mac.to_uppercase(), info.clone());
+        }
+    }
+
+    pub fn get(&self, mac: &str) -> Option<&DeviceInfo> {
+        self.lamps.get(&mac.to_uppercase())
+    }
+
+    pub fn find_ip(&self, ip: Ipv4Addr) -> Option<&DeviceInfo> {
+        self.lamps.values().find(|info| info.ip == ip)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lamps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lamps.is_empty()
+    }
+}
+
+// $XDG_CACHE_HOME/lamper/lamps.json, falling back to ~/.cache
+pub fn path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(val) if !val.is_empty() => PathBuf::from(val),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("lamper").join("lamps.json"))
+}
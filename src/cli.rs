@@ -0,0 +1,545 @@
+use std::{net::Ipv4Addr, path::PathBuf};
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::{
+    bright::{BrightMap, Curve},
+    colproc::{self, Band, Beats, Mode},
+    group::MemberOpts,
+    synth::Signal,
+    Frames,
+};
+
+// lamp picked on the command line, by ip, mac or every lamp that answers the scan
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Ip(Ipv4Addr),
+    Mac(String),
+    All,
+}
+
+impl Target {
+    pub fn parse(val: &str) -> Option<Target> {
+        if val == "all" {
+            return Some(Target::All);
+        }
+        if let Ok(ip) = val.parse() {
+            return Some(Target::Ip(ip));
+        }
+        // a mac is 6 hex pairs separated by : or -, the 8 pair device id from the govee
+        // app is the mac with two bytes in front
+        let pairs: Vec<&str> = val.split([':', '-']).collect();
+        let hex = pairs
+            .iter()
+            .all(|pair| pair.len() == 2 && pair.chars().all(|c| c.is_ascii_hexdigit()));
+        if hex && (pairs.len() == 6 || pairs.len() == 8) {
+            Some(Target::Mac(
+                pairs[pairs.len() - 6..].join(":").to_uppercase(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    // whether a device with this ip and mac is the target
+    pub fn matches(&self, ip: &Ipv4Addr, mac: &str) -> bool {
+        match self {
+            Target::Ip(val) => val == ip,
+            Target::Mac(val) => val.eq_ignore_ascii_case(&mac.replace('-', ":")),
+            Target::All => true,
+        }
+    }
+}
+
+// settings for one lamp of a group, None is left to a prompt or the default
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LampArgs {
+    pub band: Option<Band>,
+    // hue rotation in degrees
+    pub hue: Option<f32>,
+    pub maxb: Option<u8>,
+}
+
+impl LampArgs {
+    // group options for the lamp, range is the band when none was given
+    pub fn member(&self, range: Band) -> MemberOpts {
+        let default = MemberOpts::default();
+        MemberOpts {
+            band: self.band.unwrap_or(range),
+            hue: self.hue.unwrap_or(default.hue),
+            maxb: self.maxb.unwrap_or(default.maxb),
+        }
+    }
+}
+
+// what to do when a lamp can't be found or stops responding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Retry {
+    // prompt on stdin
+    Ask,
+    Always,
+    Never,
+    // retry this many times in a row then give up
+    Times(u32),
+}
+
+impl Retry {
+    pub fn parse(val: &str) -> Option<Retry> {
+        match val {
+            "ask" => Some(Retry::Ask),
+            "always" => Some(Retry::Always),
+            "never" => Some(Retry::Never),
+            _ => val.parse().ok().map(Retry::Times),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+// mode picked by name, the settings for it are separate args
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModeKind {
+    Spectrum,
+    Brightness,
+    Cycle,
+    Beat,
+}
+
+impl ModeKind {
+    pub fn parse(val: &str) -> Option<ModeKind> {
+        match val {
+            "spectrum" => Some(ModeKind::Spectrum),
+            "brightness" => Some(ModeKind::Brightness),
+            "cycle" => Some(ModeKind::Cycle),
+            "beat" => Some(ModeKind::Beat),
+            _ => None,
+        }
+    }
+}
+
+// everything that can be given on the command line or in a profile, None is left to a
+// prompt or the default
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub devices: Vec<Target>,
+    // per lamp settings, used for whichever connected lamp matches the target
+    pub lamps: Vec<(Target, LampArgs)>,
+    // local address lamps are searched from, picks the interface
+    pub bind: Option<Ipv4Addr>,
+    // govee developer api key, lamps are driven through the cloud api instead of the lan
+    pub api_key: Option<String>,
+    pub source: Option<String>,
+    // wav file or test signal played instead of capturing
+    pub wav: Option<PathBuf>,
+    pub synth: Option<Signal>,
+    pub mode: Option<ModeKind>,
+    pub band: Option<Band>,
+    pub beats: Option<Beats>,
+    pub palette: Option<Vec<[u8; 3]>>,
+    // frequency range analyzed in spectrum mode
+    pub range: Option<Band>,
+    pub window: Option<usize>,
+    // samples between the starts of windows
+    pub hop: Option<usize>,
+    // ms between commands to a lamp, sets the send rate
+    pub delay: Option<u64>,
+    pub maxb: Option<u8>,
+    // brightness the quietest level maps to, and the one nothing goes below
+    pub minb: Option<u8>,
+    pub floor: Option<u8>,
+    pub curve: Option<Curve>,
+    pub retry: Option<Retry>,
+    pub verbosity: Verbosity,
+}
+
+impl Args {
+    // parse the process arguments, prints usage and exits on bad input
+    pub fn parse() -> Self {
+        Args::from_matches(&command().get_matches())
+    }
+
+    // parse any argument list, the first item is the binary name
+    pub fn try_parse_from<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        Ok(Args::from_matches(&command().try_get_matches_from(args)?))
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        let verbosity = if matches.get_flag("quiet") {
+            Verbosity::Quiet
+        } else if matches.get_flag("verbose") {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        };
+        Args {
+            config: matches.get_one::<PathBuf>("config").cloned(),
+            profile: matches.get_one::<String>("profile").cloned(),
+            devices: matches
+                .get_many::<Target>("device")
+                .map(|devices| devices.cloned().collect())
+                .unwrap_or_default(),
+            // per lamp settings only come from a profile
+            lamps: Vec::new(),
+            bind: matches.get_one::<Ipv4Addr>("bind").copied(),
+            api_key: matches.get_one::<String>("api-key").cloned(),
+            source: matches.get_one::<String>("source").cloned(),
+            wav: matches.get_one::<PathBuf>("wav").cloned(),
+            synth: matches.get_one::<Signal>("synth").copied(),
+            mode: matches.get_one::<ModeKind>("mode").copied(),
+            band: matches.get_one::<Band>("band").copied(),
+            beats: matches.get_one::<Beats>("beats").copied(),
+            palette: matches.get_one::<Vec<[u8; 3]>>("palette").cloned(),
+            range: matches.get_one::<Band>("range").copied(),
+            window: matches.get_one::<usize>("window").copied(),
+            hop: matches.get_one::<usize>("hop").copied(),
+            delay: matches.get_one::<u64>("cmd-delay").copied(),
+            maxb: matches.get_one::<u8>("max-brightness").copied(),
+            minb: matches.get_one::<u8>("min-brightness").copied(),
+            floor: matches.get_one::<u8>("floor").copied(),
+            curve: matches.get_one::<Curve>("curve").copied(),
+            retry: matches.get_one::<Retry>("retry").copied(),
+            verbosity,
+        }
+    }
+
+    // fill whatever wasn't given from other args, a profile, values here win
+    // the audio input is one setting, a source here replaces a wav file or signal there
+    pub fn or(self, other: Args) -> Args {
+        let input = self.source.is_some() || self.wav.is_some() || self.synth.is_some();
+        let (source, wav, synth) = if input {
+            (self.source, self.wav, self.synth)
+        } else {
+            (other.source, other.wav, other.synth)
+        };
+        Args {
+            config: self.config.or(other.config),
+            profile: self.profile.or(other.profile),
+            devices: if self.devices.is_empty() {
+                other.devices
+            } else {
+                self.devices
+            },
+            lamps: if self.lamps.is_empty() {
+                other.lamps
+            } else {
+                self.lamps
+            },
+            bind: self.bind.or(other.bind),
+            api_key: self.api_key.or(other.api_key),
+            source,
+            wav,
+            synth,
+            mode: self.mode.or(other.mode),
+            band: self.band.or(other.band),
+            beats: self.beats.or(other.beats),
+            palette: self.palette.or(other.palette),
+            range: self.range.or(other.range),
+            window: self.window.or(other.window),
+            hop: self.hop.or(other.hop),
+            delay: self.delay.or(other.delay),
+            maxb: self.maxb.or(other.maxb),
+            minb: self.minb.or(other.minb),
+            floor: self.floor.or(other.floor),
+            curve: self.curve.or(other.curve),
+            retry: self.retry.or(other.retry),
+            verbosity: self.verbosity,
+        }
+    }
+
+    // the mode with its settings, defaults for any setting not given
+    pub fn mode(&self) -> Option<Mode> {
+        self.mode.map(|mode| match mode {
+            ModeKind::Spectrum => Mode::Spectrum,
+            ModeKind::Brightness => Mode::Brightness {
+                band: self.band.unwrap_or(Band::KICK),
+                colors: self.palette.clone().unwrap_or_else(|| vec![[255, 0, 0]]),
+            },
+            ModeKind::Cycle => Mode::Cycle {
+                beats: self.beats.unwrap_or(Beats::Four),
+                palette: self
+                    .palette
+                    .clone()
+                    .unwrap_or_else(|| colproc::PALETTE.to_vec()),
+            },
+            ModeKind::Beat => Mode::Beat {
+                palette: self
+                    .palette
+                    .clone()
+                    .unwrap_or_else(|| colproc::PALETTE.to_vec()),
+            },
+        })
+    }
+
+    // settings for the lamp with this ip and mac, if any were given
+    pub fn lamp(&self, ip: &Ipv4Addr, mac: &str) -> Option<LampArgs> {
+        self.lamps
+            .iter()
+            .find(|(target, _)| target.matches(ip, mac))
+            .map(|(_, lamp)| *lamp)
+    }
+
+    // analysis window and hop, defaults for either not given, None if the hop is longer
+    // than the window
+    pub fn frames(&self) -> Option<Frames> {
+        Frames::with(self.window, self.hop)
+    }
+
+    // brightness mapping with the given ceiling, defaults for any setting not given
+    pub fn bright(&self, maxb: u8) -> BrightMap {
+        BrightMap {
+            min: self.minb.unwrap_or(0),
+            max: maxb,
+            floor: self.floor.unwrap_or(0),
+            curve: self.curve.unwrap_or(Curve::Linear),
+        }
+    }
+}
+
+fn command() -> Command {
+    Command::new("lamper")
+        .about("Sync Govee lamps to audio over the LAN API, or the cloud API with --api-key")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("PATH")
+                .help("Config file, defaults to $XDG_CONFIG_HOME/lamper/config.toml")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("profile")
+                .short('P')
+                .long("profile")
+                .value_name("NAME")
+                .help("Profile from the config file, flags override its settings"),
+        )
+        .arg(
+            Arg::new("device")
+                .short('d')
+                .long("device")
+                .value_name("IP|MAC|all")
+                .help("Lamp to connect to, repeat for a group")
+                .action(ArgAction::Append)
+                .value_parser(|val: &str| Target::parse(val).ok_or("expected an ip, mac or all")),
+        )
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .value_name("IP")
+                .help("Local address to search for lamps from, picks the network interface")
+                .value_parser(clap::value_parser!(Ipv4Addr)),
+        )
+        .arg(
+            Arg::new("api-key")
+                .long("api-key")
+                .value_name("KEY")
+                .help("Govee developer API key, drive the lamps on the account through the cloud API")
+                .conflicts_with("bind"),
+        )
+        .arg(
+            Arg::new("source")
+                .short('s')
+                .long("source")
+                .value_name("NAME")
+                .help("PulseAudio source to capture, \"default\" for the server default"),
+        )
+        .arg(
+            Arg::new("wav")
+                .long("wav")
+                .value_name("PATH")
+                .help("Play a WAV file in real time instead of capturing")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all(["source", "synth"]),
+        )
+        .arg(
+            Arg::new("synth")
+                .long("synth")
+                .value_name("SIGNAL")
+                .help("Test signal instead of capturing, sine=HZ, sweep[=FROM-TO], noise or clicks[=BPM]")
+                .value_parser(|val: &str| {
+                    Signal::parse(val).ok_or("expected sine=HZ, sweep[=FROM-TO], noise or clicks[=BPM]")
+                })
+                .conflicts_with("source"),
+        )
+        .arg(
+            Arg::new("mode")
+                .short('m')
+                .long("mode")
+                .value_name("spectrum|brightness|cycle|beat")
+                .help("Processing mode")
+                .value_parser(|val: &str| {
+                    ModeKind::parse(val).ok_or("expected spectrum, brightness, cycle or beat")
+                }),
+        )
+        .arg(
+            Arg::new("band")
+                .long("band")
+                .value_name("BAND")
+                .help("Brightness mode band, full/kick/bass/mid/treble or min-max hz")
+                .value_parser(|val: &str| parse_band(val).ok_or("expected a band name or range")),
+        )
+        .arg(
+            Arg::new("beats")
+                .long("beats")
+                .value_name("1|2|4|8")
+                .help("Cycle mode beats per color")
+                .value_parser(|val: &str| parse_beats(val).ok_or("expected 1, 2, 4 or 8")),
+        )
+        .arg(
+            Arg::new("palette")
+                .short('p')
+                .long("palette")
+                .value_name("r,g,b;r,g,b...")
+                .help("Brightness mode colors or cycle and beat mode palette")
+                .value_parser(|val: &str| parse_colors(val).ok_or("expected colors like 255,0,0")),
+        )
+        .arg(
+            Arg::new("range")
+                .long("range")
+                .value_name("MIN-MAX")
+                .help("Frequency range in hz analyzed in spectrum mode")
+                .value_parser(|val: &str| parse_band(val).ok_or("expected a range like 20-20000")),
+        )
+        .arg(
+            Arg::new("window")
+                .long("window")
+                .value_name("SAMPLES")
+                .help("Analysis window size, a power of two from 256")
+                .value_parser(|val: &str| {
+                    parse_window(val).ok_or("expected a power of two of at least 256")
+                }),
+        )
+        .arg(
+            Arg::new("hop")
+                .long("hop")
+                .value_name("SAMPLES")
+                .help("Samples between the starts of analysis windows, from 256 up to the window size")
+                .value_parser(|val: &str| parse_hop(val).ok_or("expected at least 256")),
+        )
+        .arg(
+            Arg::new("cmd-delay")
+                .long("cmd-delay")
+                .value_name("MS")
+                .help("Minimum time between commands to a lamp")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("max-brightness")
+                .short('b')
+                .long("max-brightness")
+                .value_name("1-100")
+                .help("Brightness ceiling for every lamp")
+                .value_parser(clap::value_parser!(u8).range(1..=100)),
+        )
+        .arg(
+            Arg::new("min-brightness")
+                .long("min-brightness")
+                .value_name("0-100")
+                .help("Brightness the quietest sound maps to")
+                .value_parser(clap::value_parser!(u8).range(0..=100)),
+        )
+        .arg(
+            Arg::new("floor")
+                .long("floor")
+                .value_name("0-100")
+                .help("Brightness lamps never go below, keeps them lit through silence")
+                .value_parser(clap::value_parser!(u8).range(0..=100)),
+        )
+        .arg(
+            Arg::new("curve")
+                .long("curve")
+                .value_name("linear|gamma[=G]|log")
+                .help("Brightness curve between min and max brightness")
+                .value_parser(|val: &str| {
+                    Curve::parse(val).ok_or("expected linear, log, gamma or gamma=G")
+                }),
+        )
+        .arg(
+            Arg::new("retry")
+                .short('r')
+                .long("retry")
+                .value_name("ask|always|never|N")
+                .help("Retry policy when lamps are not found or stop responding")
+                .value_parser(|val: &str| {
+                    Retry::parse(val).ok_or("expected ask, always, never or a count")
+                }),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Print mode switches and status checks")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("quiet"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only print errors")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+// parse a band name or a custom range like 40-120
+pub fn parse_band(val: &str) -> Option<Band> {
+    match val {
+        "full" => Some(Band::FULL),
+        "kick" => Some(Band::KICK),
+        "bass" => Some(Band::BASS),
+        "mid" => Some(Band::MID),
+        "treble" => Some(Band::TREBLE),
+        _ => {
+            let (min, max) = val.split_once('-')?;
+            Band::new(min.trim().parse().ok()?, max.trim().parse().ok()?)
+        }
+    }
+}
+
+// parse colors like 255,0,0 separated by ;
+pub fn parse_colors(val: &str) -> Option<Vec<[u8; 3]>> {
+    val.split(';')
+        .map(|color| {
+            let rgb: Vec<u8> = color
+                .split(',')
+                .map(|c| c.trim().parse().ok())
+                .collect::<Option<_>>()?;
+            rgb.try_into().ok()
+        })
+        .collect()
+}
+
+// window sizes that Frames accepts
+pub fn parse_window(val: &str) -> Option<usize> {
+    let window: usize = val.parse().ok()?;
+    Frames::with_window(window).map(|frames| frames.window)
+}
+
+// hop sizes that Frames accepts with some window, whether it fits the window in use is
+// checked by Args::frames
+pub fn parse_hop(val: &str) -> Option<usize> {
+    let hop: usize = val.parse().ok()?;
+    Frames::new(hop.checked_next_power_of_two()?, hop).map(|frames| frames.hop)
+}
+
+pub fn parse_beats(val: &str) -> Option<Beats> {
+    match val {
+        "1" => Some(Beats::One),
+        "2" => Some(Beats::Two),
+        "4" => Some(Beats::Four),
+        "8" => Some(Beats::Eight),
+        _ => None,
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{
+    blocking::{self, RequestBuilder},
+    header::HeaderMap,
+    StatusCode,
+};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+
+use crate::{
+    protocol::Rgb,
+    udp::{Cmd, CmdErr, InitErr, Light, State, Turn},
+};
+
+// govee developer api, for models without the lan api
+// every request carries the account's api key, quotas are low (about 10 control
+// requests a minute per device and 10000 a day per account) so the client stops
+// sending once the rate limit headers say the quota is gone until it resets
+pub const URL: &str = "https://developer-api.govee.com";
+
+// wait used when the api says to slow down without saying for how long
+const BACKOFF: Duration = Duration::from_secs(60);
+// window the per device pacing counts requests over
+const PACE_WINDOW: Duration = Duration::from_secs(60);
+
+// per_minute is how many requests one device is sent in any minute, the rest are refused
+// before they reach the api
+#[derive(Debug, Clone)]
+pub struct CloudOpts {
+    pub url: String,
+    pub timeout: Duration,
+    pub per_minute: u32,
+}
+
+impl Default for CloudOpts {
+    fn default() -> Self {
+        CloudOpts {
+            url: URL.to_string(),
+            timeout: Duration::from_secs(10),
+            per_minute: 10,
+        }
+    }
+}
+
+// a device on the account as listed by the api
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudDevice {
+    pub device: String,
+    pub model: String,
+    #[serde(default)]
+    pub device_name: String,
+    #[serde(default)]
+    pub controllable: bool,
+    #[serde(default)]
+    pub retrievable: bool,
+    // api names of the commands it takes, turn, brightness, color and colorTem
+    #[serde(default)]
+    pub support_cmds: Vec<String>,
+}
+
+// every reply is {"code": ..., "message": ..., "data": ...}, code mirrors the http status
+#[derive(Debug, Deserialize)]
+struct Reply<T> {
+    code: u16,
+    #[serde(default)]
+    message: String,
+    data: Option<T>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Devices {
+    devices: Vec<CloudDevice>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Properties {
+    properties: Vec<Property>,
+}
+
+// state comes as a list of single key objects
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Property {
+    // true or "true" depending on the model
+    Online {
+        online: serde_json::Value,
+    },
+    Power {
+        #[serde(rename = "powerState")]
+        power_state: String,
+    },
+    Brightness {
+        brightness: u64,
+    },
+    Color {
+        color: Rgb,
+    },
+    Temp {
+        #[serde(rename = "colorTem", alias = "colorTemInKelvin")]
+        temp: u16,
+    },
+    Other(IgnoredAny),
+}
+
+#[derive(Debug, Serialize)]
+struct Control<'a> {
+    device: &'a str,
+    model: &'a str,
+    cmd: CloudCmd,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "name", content = "value", rename_all = "camelCase")]
+enum CloudCmd {
+    Turn(&'static str),
+    Brightness(u8),
+    Color(Rgb),
+    ColorTem(u16),
+}
+
+// api key and quota state, clones share the quota
+// requests block, so it can't be used from inside a tokio runtime
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: blocking::Client,
+    key: String,
+    url: String,
+    // no requests before this
+    until: Arc<Mutex<Option<SystemTime>>>,
+    // recent requests to each device by id, within PACE_WINDOW
+    sent: Arc<Mutex<HashMap<String, VecDeque<Instant>>>>,
+    per_minute: u32,
+}
+
+// a device reached through the api, same as udp::Lamp through the Light trait
+#[derive(Debug)]
+pub struct CloudLamp {
+    client: Client,
+    pub info: CloudDevice,
+    pub init: State,
+}
+
+impl CloudDevice {
+    // mac as the lan api gives it, the device id is the mac with two bytes in front
+    pub fn mac(&self) -> String {
+        let pairs: Vec<&str> = self.device.split(':').collect();
+        pairs[pairs.len().saturating_sub(6)..].join(":")
+    }
+}
+
+impl CloudCmd {
+    fn name(&self) -> &'static str {
+        match self {
+            CloudCmd::Turn(_) => "turn",
+            CloudCmd::Brightness(_) => "brightness",
+            CloudCmd::Color(_) => "color",
+            CloudCmd::ColorTem(_) => "colorTem",
+        }
+    }
+}
+
+impl TryFrom<Cmd> for CloudCmd {
+    type Error = CmdErr;
+
+    fn try_from(cmd: Cmd) -> Result<Self, CmdErr> {
+        Ok(match cmd {
+            Cmd::OnOff(Turn::On) => CloudCmd::Turn("on"),
+            Cmd::OnOff(Turn::Off) => CloudCmd::Turn("off"),
+            Cmd::Brightness(val) if val > 100 => return Err(CmdErr::InvalidBrightnessErr(val)),
+            Cmd::Brightness(val) => CloudCmd::Brightness(val),
+            Cmd::Color(val) => CloudCmd::Color(val.into()),
+            Cmd::Temp(val) => CloudCmd::ColorTem(val),
+        })
+    }
+}
+
+impl TryFrom<Properties> for State {
+    type Error = CmdErr;
+
+    // power and brightness have to be there, color mode is whichever was reported
+    fn try_from(props: Properties) -> Result<Self, CmdErr> {
+        let mut pwr = None;
+        let mut bright = None;
+        let mut color = [0, 0, 0];
+        let mut temp = 0;
+        for prop in props.properties {
+            match prop {
+                Property::Online { online } => {
+                    if online != true && online != "true" {
+                        return Err(CmdErr::OfflineErr);
+                    }
+                }
+                Property::Power { power_state } => {
+                    pwr = Some(match power_state.as_str() {
+                        "on" => Turn::On,
+                        "off" => Turn::Off,
+                        val => return Err(CmdErr::SerdeErr(format!("powerState {}", val))),
+                    })
+                }
+                Property::Brightness { brightness } => {
+                    bright = Some(
+                        u8::try_from(brightness)
+                            .ok()
+                            .filter(|val| *val <= 100)
+                            .ok_or(CmdErr::InvalidFieldErr {
+                                field: "brightness",
+                                value: brightness,
+                            })?,
+                    )
+                }
+                Property::Color { color: rgb } => color = rgb.into(),
+                Property::Temp { temp: val } => temp = val,
+                Property::Other(_) => {}
+            }
+        }
+        match (pwr, bright) {
+            (Some(pwr), Some(bright)) => Ok(State {
+                pwr,
+                bright,
+                color,
+                temp,
+            }),
+            _ => Err(CmdErr::SerdeErr(
+                "state without powerState or brightness".to_string(),
+            )),
+        }
+    }
+}
+
+impl From<reqwest::Error> for CmdErr {
+    fn from(err: reqwest::Error) -> Self {
+        CmdErr::HttpErr(err.to_string())
+    }
+}
+
+impl Client {
+    pub fn new(key: &str) -> Result<Client, CmdErr> {
+        Client::with_opts(key, CloudOpts::default())
+    }
+
+    pub fn with_opts(key: &str, opts: CloudOpts) -> Result<Client, CmdErr> {
+        Ok(Client {
+            http: blocking::Client::builder().timeout(opts.timeout).build()?,
+            key: key.to_string(),
+            url: opts.url.trim_end_matches('/').to_string(),
+            until: Arc::new(Mutex::new(None)),
+            sent: Arc::new(Mutex::new(HashMap::new())),
+            per_minute: opts.per_minute.max(1),
+        })
+    }
+
+    // every device on the account
+    pub fn devices(&self) -> Result<Vec<CloudDevice>, CmdErr> {
+        let devices: Devices = self.call(self.http.get(format!("{}/v1/devices", self.url)))?;
+        Ok(devices.devices)
+    }
+
+    pub fn state(&self, info: &CloudDevice) -> Result<State, CmdErr> {
+        let req = self
+            .http
+            .get(format!("{}/v1/devices/state", self.url))
+            .query(&[("device", &info.device), ("model", &info.model)]);
+        self.pace(info)?;
+        State::try_from(self.call::<Properties>(req)?)
+    }
+
+    // commands the device doesn't list are refused without using up quota
+    pub fn control(&self, info: &CloudDevice, cmd: Cmd) -> Result<(), CmdErr> {
+        let cmd = CloudCmd::try_from(cmd)?;
+        if !info.support_cmds.is_empty() && !info.support_cmds.iter().any(|name| name == cmd.name())
+        {
+            return Err(CmdErr::UnsupportedErr(cmd.name()));
+        }
+        let req = self
+            .http
+            .put(format!("{}/v1/devices/control", self.url))
+            .json(&Control {
+                device: &info.device,
+                model: &info.model,
+                cmd,
+            });
+        self.pace(info)?;
+        self.call::<IgnoredAny>(req)?;
+        Ok(())
+    }
+
+    // read the device's state to restore later
+    pub fn connect(&self, info: &CloudDevice) -> Result<CloudLamp, InitErr> {
+        let init = self.state(info)?;
+        Ok(CloudLamp {
+            client: self.clone(),
+            info: info.clone(),
+            init,
+        })
+    }
+
+    // how long until requests are allowed again, None if they are now
+    pub fn limited(&self) -> Option<Duration> {
+        let until = (*self.until.lock().unwrap())?;
+        until.duration_since(SystemTime::now()).ok()
+    }
+
+    // how long until the device can be sent another request, None if it can now
+    pub fn paced(&self, info: &CloudDevice) -> Option<Duration> {
+        let mut sent = self.sent.lock().unwrap();
+        let times = sent.get_mut(&info.device)?;
+        let now = Instant::now();
+        while times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= PACE_WINDOW)
+        {
+            times.pop_front();
+        }
+        if times.len() < self.per_minute as usize {
+            return None;
+        }
+        times.front().map(|t| PACE_WINDOW - now.duration_since(*t))
+    }
+
+    // count a request to the device, refused if it already had per_minute this minute or
+    // the account is out of quota
+    fn pace(&self, info: &CloudDevice) -> Result<(), CmdErr> {
+        if let Some(wait) = self.limited().or_else(|| self.paced(info)) {
+            return Err(CmdErr::RateLimitErr(wait));
+        }
+        self.sent
+            .lock()
+            .unwrap()
+            .entry(info.device.clone())
+            .or_default()
+            .push_back(Instant::now());
+        Ok(())
+    }
+
+    fn call<T: DeserializeOwned + Default>(&self, req: RequestBuilder) -> Result<T, CmdErr> {
+        if let Some(wait) = self.limited() {
+            return Err(CmdErr::RateLimitErr(wait));
+        }
+        let res = req.header("Govee-API-Key", &self.key).send()?;
+        let status = res.status();
+        self.track(status, res.headers());
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(CmdErr::RateLimitErr(self.limited().unwrap_or(BACKOFF)));
+        }
+        let body = res.bytes()?;
+        let reply: Reply<T> = match serde_json::from_slice(&body) {
+            Ok(reply) => reply,
+            // errors from in front of the api aren't always json
+            Err(_) if !status.is_success() => {
+                return Err(CmdErr::ApiErr {
+                    code: status.as_u16(),
+                    msg: String::from_utf8_lossy(&body).to_string(),
+                })
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if !status.is_success() || reply.code != 200 {
+            return Err(CmdErr::ApiErr {
+                code: reply.code,
+                msg: reply.message,
+            });
+        }
+        Ok(reply.data.unwrap_or_default())
+    }
+
+    // hold off until the reset time of any quota that ran out, resets are unix seconds
+    fn track(&self, status: StatusCode, headers: &HeaderMap) {
+        let header =
+            |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.trim().parse().ok() };
+        let mut until = None;
+        for (remaining, reset) in [
+            ("X-RateLimit-Remaining", "X-RateLimit-Reset"),
+            ("API-RateLimit-Remaining", "API-RateLimit-Reset"),
+        ] {
+            if header(remaining) == Some(0) {
+                if let Some(reset) = header(reset) {
+                    let reset = UNIX_EPOCH + Duration::from_secs(reset);
+                    until = until.max(Some(reset));
+                }
+            }
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry =
+                header("Retry-After").map(|secs| SystemTime::now() + Duration::from_secs(secs));
+            until = until
+                .max(retry)
+                .or_else(|| Some(SystemTime::now() + BACKOFF));
+        }
+        if until.is_some() {
+            let mut held = self.until.lock().unwrap();
+            *held = (*held).max(until);
+        }
+    }
+}
+
+impl CloudLamp {
+    fn wait_pace(&self) {
+        if let Some(wait) = self.client.paced(&self.info) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+impl Light for CloudLamp {
+    fn send_cmd(&self, cmd: Cmd) -> Result<(), CmdErr> {
+        self.client.control(&self.info, cmd)
+    }
+
+    fn status(&self) -> Result<State, CmdErr> {
+        self.client.state(&self.info)
+    }
+
+    fn init(&self) -> &State {
+        &self.init
+    }
+
+    // every request counts against the quota, so each command goes once and the state
+    // is read back once, restoring is the last thing sent so it waits out the pacing
+    fn restore(&self) -> Result<(), CmdErr> {
+        for cmd in self.init.cmds() {
+            self.wait_pace();
+            match self.send_cmd(cmd) {
+                Ok(_) | Err(CmdErr::UnsupportedErr(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.wait_pace();
+        match self.status() {
+            Ok(read) if self.init.restored(&read) => Ok(()),
+            Ok(_) => Err(CmdErr::RestoreErr),
+            Err(err) => Err(err),
+        }
+    }
+}
@@ -1,190 +1,345 @@
-// todo:
-// fix normalization
-// two modes, one with hz -> color and another with hz -> brightness
-
-use dft::{Operation, Plan};
 use rand::{self, Rng};
 use std::sync::{
     mpsc::{Receiver, RecvError, Sender},
     Arc, RwLock,
 };
 
-use crate::{LampErr, WINDOW};
+use crate::{
+    agc::{self, Agc, AgcOpts},
+    beat::{Onset, Tempo},
+    health::Status,
+    spectrum::{BandAnalyzer, Hue, Scale, Spectrum, Window},
+    Frames, LampErr, RATE,
+};
 
 // frequency range
 const MAX_FREQUENCY: f32 = 20000.0;
 const MIN_FREQUENCY: f32 = 20.0;
 
+// band spacing and hue source for color, third octave bands by default
+const SCALE: Scale = Scale::Octave(3);
+const HUE: Hue = Hue::Centroid;
+// degrees of the color wheel used, stops short of wrapping back to red
+const HUE_RANGE: f32 = 300.0;
+
 impl From<RecvError> for LampErr {
     fn from(_: RecvError) -> Self {
         LampErr::SendErr
     }
 }
 
-// brightness normalization
-struct BrightNorm {
-    default: f32,
-    max: f32,
-    reset: u8,
-    reset_end: u8,
-}
-impl BrightNorm {
-    fn new() -> Self {
-        BrightNorm {
-            default: 800.0,
-            max: 0.0,
-            reset: 0,
-            reset_end: 50,
+// frequency band in hz
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Band {
+    pub const FULL: Band = Band {
+        min: MIN_FREQUENCY,
+        max: MAX_FREQUENCY,
+    };
+    pub const KICK: Band = Band {
+        min: 40.0,
+        max: 120.0,
+    };
+    pub const BASS: Band = Band {
+        min: MIN_FREQUENCY,
+        max: 250.0,
+    };
+    pub const MID: Band = Band {
+        min: 250.0,
+        max: 4000.0,
+    };
+    pub const TREBLE: Band = Band {
+        min: 4000.0,
+        max: MAX_FREQUENCY,
+    };
+
+    // custom band, both bounds finite and min above 0 and below max, max is held at
+    // MAX_FREQUENCY
+    pub fn new(min: f32, max: f32) -> Option<Band> {
+        if !min.is_finite() || !max.is_finite() {
+            return None;
         }
-    }
-    // add conditions for reset_end
-    fn norm(&mut self, vol: f32) -> u8 {
-        if self.max != 0.0 && self.reset >= self.reset_end && vol < self.default {
-            self.max = 0.0;
-            self.reset = 0;
-            ((vol / self.default) * 100.0) as u8
-        } else if self.max != 0.0 && (vol > self.max || vol > self.default) {
-            self.max = vol;
-            self.reset = 0;
-            100u8
-        } else if self.max != 0.0 {
-            self.reset += 1;
-            ((vol / self.max) * 100.0) as u8
-        } else if vol > self.default {
-            self.max = vol;
-            self.reset = 0;
-            100u8
+        let max = max.min(MAX_FREQUENCY);
+        if min > 0.0 && min < max {
+            Some(Band { min, max })
         } else {
-            self.reset = 0;
-            ((vol / self.default) * 100.0) as u8
+            None
+        }
+    }
+
+    // band analyzer spanning this band, colors come from where the energy sits within it
+    fn analyzer(&self, frames: Frames) -> BandAnalyzer {
+        BandAnalyzer::new(SCALE, self.min, self.max, frames.window / 2, RATE)
+    }
+}
+
+// what drives the lamp, shared with main so it can be switched while running
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    // hz -> color, loudness -> brightness
+    Spectrum,
+    // energy in band -> brightness, color held or picked from a palette by brightness
+    Brightness { band: Band, colors: Vec<[u8; 3]> },
+    // loudness -> brightness, random color from the palette every few beats
+    Cycle { beats: Beats, palette: Vec<[u8; 3]> },
+    // full brightness flash and a new color from the palette on every onset
+    Beat { palette: Vec<[u8; 3]> },
+}
+
+// output of every processing mode, a lamp update with brightness, color or both
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Update {
+    Both(u8, [u8; 3]),
+    Brightness(u8),
+    Color([u8; 3]),
+}
+
+impl Update {
+    // combine with a newer update, the newer values win and nothing is lost
+    pub fn merge(self, newer: Update) -> Update {
+        match (self, newer) {
+            (_, Update::Both(b, c)) => Update::Both(b, c),
+            (Update::Both(_, c) | Update::Color(c), Update::Brightness(b)) => Update::Both(b, c),
+            (Update::Brightness(_), Update::Brightness(b)) => Update::Brightness(b),
+            (Update::Both(b, _) | Update::Brightness(b), Update::Color(c)) => Update::Both(b, c),
+            (Update::Color(_), Update::Color(c)) => Update::Color(c),
         }
     }
 }
 
-// main fn to process audio into brightness and rgb
-pub fn process(
+// main fn to process audio into lamp updates, one per band in the order given, following the
+// shared mode, in brightness, cycle and beat mode every band gets the same update
+pub fn process_bands(
     rx: Receiver<Vec<f32>>,
-    tx: Sender<(u8, [u8; 3])>,
-    conn: Arc<RwLock<bool>>,
+    tx: Sender<Vec<Update>>,
+    status: Arc<RwLock<Status>>,
+    mode: Arc<RwLock<Mode>>,
+    bands: Vec<Band>,
+    frames: Frames,
 ) -> Result<(), LampErr> {
-    let mut bright_norm = BrightNorm::new();
+    let mut agcs: Vec<Agc> = bands
+        .iter()
+        .map(|_| Agc::new(AgcOpts::default(), frames.fps()))
+        .collect();
+    let mut analyzers: Vec<BandAnalyzer> = bands.iter().map(|b| b.analyzer(frames)).collect();
+    let mut spectrum = Spectrum::new(frames.window, Window::Hann);
+    // brightness mode analyzer, rebuilt whenever the mode's band changes
+    let mut held: Option<(Band, BandAnalyzer, Agc)> = None;
+    let mut cycle = Cycler::new(frames);
+    let mut flash = Flasher::new(frames);
     loop {
-        if !*conn.read().unwrap() {
+        if !status.read().unwrap().running() {
             return Ok(());
         }
 
         let data = rx.recv()?;
-        let freqs = dft(data);
-        let mut top_freq = 0.0;
-        let mut top_freq_vol = 0.0;
-
-        for (i, volume) in freqs.iter().enumerate() {
-            if i > 0 && i < freqs.len() / 2 && volume >= &top_freq_vol {
-                top_freq = i as f32 * 44100_f32 / freqs.len() as f32;
-                top_freq_vol = *volume;
+        let freqs = spectrum.magnitude(&data);
+        let out = match &*mode.read().unwrap() {
+            Mode::Spectrum => {
+                let mut out = Vec::with_capacity(bands.len());
+                for (agc, analyzer) in agcs.iter_mut().zip(analyzers.iter_mut()) {
+                    analyzer.analyze(freqs);
+                    let brightness = agc.process(analyzer.level());
+                    out.push(Update::Both(brightness, rgb(analyzer.position(HUE))));
+                }
+                out
+            }
+            Mode::Brightness { band, colors } => {
+                if held.as_ref().map(|(b, _, _)| b) != Some(band) {
+                    let agc = Agc::new(AgcOpts::default(), frames.fps());
+                    held = Some((*band, band.analyzer(frames), agc));
+                }
+                let (_, analyzer, agc) = held.as_mut().unwrap();
+                analyzer.analyze(freqs);
+                let brightness = agc.process(analyzer.level());
+                vec![Update::Both(brightness, palette(colors, brightness)); bands.len()]
             }
+            Mode::Cycle { beats, palette } => {
+                vec![cycle.next(&data, freqs, *beats, palette); bands.len()]
+            }
+            Mode::Beat { palette } => vec![flash.next(&data, freqs, palette); bands.len()],
+        };
+
+        tx.send(out)?;
+    }
+}
+
+// color from a palette by brightness, the first color at 0 and the last at 100
+fn palette(colors: &[[u8; 3]], brightness: u8) -> [u8; 3] {
+    match colors.len() {
+        0 => [255, 255, 255],
+        len => colors[(brightness as usize * len / 101).min(len - 1)],
+    }
+}
+
+// seconds for a beat flash to fade to half brightness
+const FLASH_HALF_LIFE: f32 = 0.13;
+
+// beat mode state, flashes to full brightness and switches color on every detected onset
+struct Flasher {
+    onset: Onset,
+    agc: Agc,
+    color_index: Option<usize>,
+    flash: f32,
+    decay: f32,
+}
+
+impl Flasher {
+    fn new(frames: Frames) -> Self {
+        Flasher {
+            onset: Onset::new(frames.fps()),
+            agc: Agc::new(AgcOpts::default(), frames.fps()),
+            color_index: None,
+            flash: 0.0,
+            decay: 0.5f32.powf(1.0 / (FLASH_HALF_LIFE * frames.fps())),
         }
+    }
+
+    fn next(&mut self, data: &[f32], freqs: &[f32], palette: &[[u8; 3]]) -> Update {
+        // between beats the lamp sits at half the normalized level
+        let base = self.agc.process(agc::level(data)) as f32 / 2.0;
 
-        let brightness = bright_norm.norm(top_freq_vol);
-        let rgb = rgb(top_freq);
+        // palette may have changed under us, the first frame always sets a color
+        if self.color_index.is_none_or(|index| index >= palette.len()) {
+            self.color_index = Some(cycle_color(palette, None).1);
+        }
+        if self.onset.detect(freqs) {
+            self.flash = 100.0;
+            self.color_index = Some(cycle_color(palette, self.color_index).1);
+        } else {
+            self.flash *= self.decay;
+        }
 
-        tx.send((brightness, rgb))?;
-        println!(
-            "top freq: {}, top freq vol: {}, max: {}, brightness: {}",
-            top_freq, top_freq_vol, bright_norm.max, brightness
-        );
+        let color = self
+            .color_index
+            .and_then(|index| palette.get(index).copied())
+            .unwrap_or([255, 255, 255]);
+        Update::Both(self.flash.max(base) as u8, color)
     }
 }
 
-pub enum Cycle {
-    Brightness(u8),
-    Color([u8; 3]),
+// beats between color changes in cycle mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Beats {
+    One = 1,
+    Two = 2,
+    Four = 4,
+    Eight = 8,
 }
 
-pub fn process_cycle(
-    rx: Receiver<Vec<f32>>,
-    tx: Sender<Cycle>,
-    conn: Arc<RwLock<bool>>,
-) -> Result<(), LampErr> {
-    let (mut color, mut color_index) = cycle_color(None);
-    let mut cycle_count: u8 = 0;
-    let cycle_end: u8 = 255;
-    let mut bright_norm = BrightNorm::new();
-    loop {
-        if !*conn.read().unwrap() {
-            return Ok(());
+// default cycle mode palette
+pub const PALETTE: [[u8; 3]; 7] = [
+    [255, 0, 0],
+    [255, 0, 213],
+    [94, 0, 255],
+    [0, 26, 255],
+    [0, 213, 255],
+    [26, 255, 0],
+    [255, 111, 0],
+];
+
+// seconds between color changes until the tempo locks
+const CYCLE_SECS: f32 = 24.0;
+
+// cycle mode state, brightness follows volume and the color changes every few beats
+struct Cycler {
+    onset: Onset,
+    tempo: Tempo,
+    agc: Agc,
+    color_index: Option<usize>,
+    cycle_end: u32,
+    frame_count: u32,
+    beat_count: u8,
+}
+
+impl Cycler {
+    fn new(frames: Frames) -> Self {
+        Cycler {
+            onset: Onset::new(frames.fps()),
+            tempo: Tempo::new(frames.fps()),
+            agc: Agc::new(AgcOpts::default(), frames.fps()),
+            color_index: None,
+            cycle_end: (CYCLE_SECS * frames.fps()) as u32,
+            frame_count: 0,
+            beat_count: 0,
         }
-        match cycle_count {
-            0 => {
-                rx.recv()?;
-                tx.send(Cycle::Color(color))?;
-                cycle_count += 1
-            }
-            v if v < cycle_end => {
-                let data = rx.recv()?;
-                let vol = data.iter().sum::<f32>() / data.len() as f32;
-                let brightness = bright_norm.norm(vol);
-                tx.send(Cycle::Brightness(brightness))?;
-            }
-            _ => {
-                rx.recv()?;
-                (color, color_index) = cycle_color(Some(color_index));
-                tx.send(Cycle::Color(color))?;
-            }
+    }
+
+    fn next(&mut self, data: &[f32], freqs: &[f32], beats: Beats, palette: &[[u8; 3]]) -> Update {
+        let level = agc::level(data);
+        self.onset.detect(freqs);
+        let beat = self.tempo.update(self.onset.flux());
+        self.frame_count = self.frame_count.saturating_add(1);
+        if beat {
+            self.beat_count += 1;
+        }
+
+        // palette may have changed under us, the first frame always sets a color
+        let change = match (self.color_index, self.tempo.bpm()) {
+            (Some(index), _) if index >= palette.len() => true,
+            (None, _) => true,
+            (_, Some(_)) => self.beat_count >= beats as u8,
+            (_, None) => self.frame_count >= self.cycle_end,
+        };
+        if change {
+            let (color, index) = cycle_color(palette, self.color_index);
+            self.color_index = Some(index);
+            self.frame_count = 0;
+            self.beat_count = 0;
+            Update::Color(color)
+        } else {
+            Update::Brightness(self.agc.process(level))
         }
     }
 }
 
-fn cycle_color(prev: Option<usize>) -> ([u8; 3], usize) {
-    let cycle: [[u8; 3]; 7] = [
-        [255, 0, 0],
-        [255, 0, 213],
-        [94, 0, 255],
-        [0, 26, 255],
-        [0, 213, 255],
-        [26, 255, 0],
-        [255, 111, 0],
-    ];
+// random color from the palette, different from the previous one when possible
+fn cycle_color(palette: &[[u8; 3]], prev: Option<usize>) -> ([u8; 3], usize) {
+    if palette.is_empty() {
+        return ([255, 255, 255], 0);
+    }
     let index = match prev {
-        Some(val) => {
-            let mut rand = rand::thread_rng().gen_range(0..cycle.len());
+        Some(val) if palette.len() > 1 => {
+            let mut rand = rand::thread_rng().gen_range(0..palette.len());
             while val == rand {
-                rand = rand::thread_rng().gen_range(0..cycle.len());
+                rand = rand::thread_rng().gen_range(0..palette.len());
             }
             rand
         }
-        None => rand::thread_rng().gen_range(0..cycle.len()),
+        _ => rand::thread_rng().gen_range(0..palette.len()),
     };
-    (cycle[index], index)
-}
-
-// converts the dominant frequency of each frame to hsl then rgb
-fn rgb(hz: f32) -> [u8; 3] {
-    let hue = (hz - MIN_FREQUENCY) / (MAX_FREQUENCY - MIN_FREQUENCY) * 360.0;
-    let saturation: f32 = 1.0;
-    let lightness: f32 = 0.5;
-
-    let hsl_to_rgb = |h: f32, s: f32, l: f32| {
-        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
-        let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
-        let m = l - c / 2.0;
-
-        if h < 60.0 {
-            [c + m, x + m, m]
-        } else if h < 120.0 {
-            [x + m, c + m, m]
-        } else if h < 180.0 {
-            [m, c + m, x + m]
-        } else if h < 240.0 {
-            [m, x + m, c + m]
-        } else if h < 300.0 {
-            [x + m, m, c + m]
-        } else {
-            [c + m, m, x + m]
-        }
+    (palette[index], index)
+}
+
+// converts a position across the bands, 0 to 1, to hsl then rgb
+fn rgb(position: f32) -> [u8; 3] {
+    let hue = position.clamp(0.0, 1.0) * HUE_RANGE;
+    hsl_to_rgb(hue, 1.0, 0.5)
+}
+
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> [u8; 3] {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+
+    let rgb_f = if h < 60.0 {
+        [c + m, x + m, m]
+    } else if h < 120.0 {
+        [x + m, c + m, m]
+    } else if h < 180.0 {
+        [m, c + m, x + m]
+    } else if h < 240.0 {
+        [m, x + m, c + m]
+    } else if h < 300.0 {
+        [x + m, m, c + m]
+    } else {
+        [c + m, m, x + m]
     };
 
-    let rgb_f = hsl_to_rgb(hue, saturation, lightness);
     let mut rgb: [u8; 3] = [0u8; 3];
     let max = u8::MAX as f32;
 
@@ -195,20 +350,25 @@ fn rgb(hz: f32) -> [u8; 3] {
     rgb
 }
 
-// perform dft on each frame
-fn dft(data: Vec<f32>) -> Vec<f32> {
-    let plan = Plan::new(Operation::Forward, WINDOW);
-
-    let mut input = Vec::with_capacity(WINDOW);
-    for x in data {
-        input.push(x as f64);
+// rotates the hue of an rgb color by deg degrees, keeping saturation and lightness
+pub fn rotate_hue(rgb: [u8; 3], deg: f32) -> [u8; 3] {
+    let [r, g, b] = rgb.map(|c| c as f32 / u8::MAX as f32);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+    if d == 0.0 {
+        return rgb;
     }
 
-    dft::transform(&mut input, &plan);
-    let output = dft::unpack(&input);
-    let mut result = Vec::with_capacity(WINDOW);
-    for ref c in output {
-        result.push(c.norm() as f32);
-    }
-    result
+    let s = d / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * (((g - b) / d).rem_euclid(6.0))
+    } else if max == g {
+        60.0 * ((b - r) / d + 2.0)
+    } else {
+        60.0 * ((r - g) / d + 4.0)
+    };
+
+    hsl_to_rgb((h + deg).rem_euclid(360.0), s, l)
 }
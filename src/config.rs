@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    bright::Curve,
+    cli::{self, Args, LampArgs, ModeKind, Retry, Target},
+    colproc::Band,
+    synth::Signal,
+    Frames,
+};
+
+#[derive(Debug)]
+pub enum ConfigErr {
+    NotFoundErr,
+    ReadErr,
+    ParseErr(toml::de::Error),
+    // profile asked for isn't in the file
+    ProfileErr,
+    // a profile setting is out of range, holds the key
+    ValueErr(&'static str),
+}
+
+impl From<io::Error> for ConfigErr {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => ConfigErr::NotFoundErr,
+            _ => ConfigErr::ReadErr,
+        }
+    }
+}
+
+impl From<toml::de::Error> for ConfigErr {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigErr::ParseErr(err)
+    }
+}
+
+// config file, profiles by name and the one used when none is asked for
+//
+// default = "party"
+//
+// [profiles.party]
+// devices = ["192.168.1.20", "AA:BB:CC:DD:EE:FF:00:11"]
+// bind = "192.168.1.2"
+// api_key = "00000000-0000-0000-0000-000000000000"
+// source = "alsa_output.pci-0000_00_1f.3.analog-stereo.monitor"
+// or wav = "song.wav" or synth = "clicks=120", a profile has one audio input
+// mode = "cycle"
+// beats = 4
+// palette = [[255, 0, 0], [0, 0, 255]]
+// min_frequency = 20.0
+// max_frequency = 8000.0
+// window = 4096
+// hop = 1024
+// cmd_delay = 46
+// max_brightness = 80
+// min_brightness = 10
+// floor = 5
+// curve = "gamma=2.2"
+// retry = "always"
+//
+// [[profiles.party.lamps]]
+// device = "192.168.1.20"
+// band = "bass"
+// hue = 120
+// max_brightness = 60
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub devices: Vec<String>,
+    pub bind: Option<Ipv4Addr>,
+    pub api_key: Option<String>,
+    pub source: Option<String>,
+    pub wav: Option<PathBuf>,
+    pub synth: Option<String>,
+    pub mode: Option<String>,
+    pub band: Option<String>,
+    pub beats: Option<u8>,
+    pub palette: Option<Vec<[u8; 3]>>,
+    pub min_frequency: Option<f32>,
+    pub max_frequency: Option<f32>,
+    pub window: Option<usize>,
+    pub hop: Option<usize>,
+    pub cmd_delay: Option<u64>,
+    pub max_brightness: Option<u8>,
+    pub min_brightness: Option<u8>,
+    pub floor: Option<u8>,
+    pub curve: Option<String>,
+    pub retry: Option<String>,
+    // per lamp settings, their devices are connected to along with devices
+    pub lamps: Vec<LampProfile>,
+}
+
+// one lamp of a group, picked by ip or mac
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LampProfile {
+    pub device: String,
+    pub band: Option<String>,
+    pub hue: Option<f32>,
+    pub max_brightness: Option<u8>,
+}
+
+impl Config {
+    pub fn parse(val: &str) -> Result<Config, ConfigErr> {
+        Ok(toml::from_str(val)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigErr> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    // profile by name, or the default one if there is one
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>, ConfigErr> {
+        match name.or(self.default.as_deref()) {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .map(Some)
+                .ok_or(ConfigErr::ProfileErr),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Profile {
+    // settings as args, checked the same way as the matching flags
+    pub fn args(&self) -> Result<Args, ConfigErr> {
+        let mut devices: Vec<Target> = self
+            .devices
+            .iter()
+            .map(|val| Target::parse(val).ok_or(ConfigErr::ValueErr("devices")))
+            .collect::<Result<_, _>>()?;
+        let lamps = self
+            .lamps
+            .iter()
+            .map(LampProfile::args)
+            .collect::<Result<Vec<_>, _>>()?;
+        for (target, _) in lamps.iter() {
+            if !devices.contains(target) {
+                devices.push(target.clone());
+            }
+        }
+        // one audio input, like the flags
+        if self.wav.is_some() && (self.source.is_some() || self.synth.is_some()) {
+            return Err(ConfigErr::ValueErr("wav"));
+        }
+        let synth = match &self.synth {
+            Some(_) if self.source.is_some() => return Err(ConfigErr::ValueErr("synth")),
+            Some(val) => Some(Signal::parse(val).ok_or(ConfigErr::ValueErr("synth"))?),
+            None => None,
+        };
+        let mode = match &self.mode {
+            Some(val) => Some(ModeKind::parse(val).ok_or(ConfigErr::ValueErr("mode"))?),
+            None => None,
+        };
+        let band = match &self.band {
+            Some(val) => Some(cli::parse_band(val).ok_or(ConfigErr::ValueErr("band"))?),
+            None => None,
+        };
+        let beats = match self.beats {
+            Some(val) => {
+                Some(cli::parse_beats(&val.to_string()).ok_or(ConfigErr::ValueErr("beats"))?)
+            }
+            None => None,
+        };
+        let range = match (self.min_frequency, self.max_frequency) {
+            (None, None) => None,
+            (min, max) => {
+                let max = max.unwrap_or(Band::FULL.max);
+                if !max.is_finite() {
+                    return Err(ConfigErr::ValueErr("max_frequency"));
+                }
+                let min = min.unwrap_or(Band::FULL.min);
+                Some(Band::new(min, max).ok_or(ConfigErr::ValueErr("min_frequency"))?)
+            }
+        };
+        let window = match self.window {
+            Some(val) => {
+                Some(cli::parse_window(&val.to_string()).ok_or(ConfigErr::ValueErr("window"))?)
+            }
+            None => None,
+        };
+        let hop = match self.hop {
+            Some(val) => {
+                let hop = cli::parse_hop(&val.to_string());
+                // the window is checked first, so a hop that doesn't fit is the hop's fault
+                if hop.is_none() || Frames::with(window, hop).is_none() {
+                    return Err(ConfigErr::ValueErr("hop"));
+                }
+                hop
+            }
+            None => None,
+        };
+        let maxb = match self.max_brightness {
+            Some(val) if val == 0 || val > 100 => {
+                return Err(ConfigErr::ValueErr("max_brightness"))
+            }
+            val => val,
+        };
+        if matches!(self.min_brightness, Some(val) if val > 100) {
+            return Err(ConfigErr::ValueErr("min_brightness"));
+        }
+        if matches!(self.floor, Some(val) if val > 100) {
+            return Err(ConfigErr::ValueErr("floor"));
+        }
+        let curve = match &self.curve {
+            Some(val) => Some(Curve::parse(val).ok_or(ConfigErr::ValueErr("curve"))?),
+            None => None,
+        };
+        let retry = match &self.retry {
+            Some(val) => Some(Retry::parse(val).ok_or(ConfigErr::ValueErr("retry"))?),
+            None => None,
+        };
+        if matches!(&self.palette, Some(palette) if palette.is_empty()) {
+            return Err(ConfigErr::ValueErr("palette"));
+        }
+        Ok(Args {
+            devices,
+            lamps,
+            bind: self.bind,
+            api_key: self.api_key.clone(),
+            source: self.source.clone(),
+            wav: self.wav.clone(),
+            synth,
+            mode,
+            band,
+            beats,
+            palette: self.palette.clone(),
+            range,
+            window,
+            hop,
+            delay: self.cmd_delay,
+            maxb,
+            minb: self.min_brightness,
+            floor: self.floor,
+            curve,
+            retry,
+            ..Args::default()
+        })
+    }
+}
+
+impl LampProfile {
+    // the lamp's target and settings, checked the same way as the prompts
+    fn args(&self) -> Result<(Target, LampArgs), ConfigErr> {
+        let target = match Target::parse(&self.device) {
+            Some(Target::All) | None => return Err(ConfigErr::ValueErr("device")),
+            Some(target) => target,
+        };
+        let band = match &self.band {
+            Some(val) => Some(cli::parse_band(val).ok_or(ConfigErr::ValueErr("band"))?),
+            None => None,
+        };
+        if matches!(self.hue, Some(val) if !(0.0..360.0).contains(&val)) {
+            return Err(ConfigErr::ValueErr("hue"));
+        }
+        if matches!(self.max_brightness, Some(val) if val == 0 || val > 100) {
+            return Err(ConfigErr::ValueErr("max_brightness"));
+        }
+        let lamp = LampArgs {
+            band,
+            hue: self.hue,
+            maxb: self.max_brightness,
+        };
+        Ok((target, lamp))
+    }
+}
+
+// $XDG_CONFIG_HOME/lamper/config.toml, falling back to ~/.config
+pub fn path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(val) if !val.is_empty() => PathBuf::from(val),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("lamper").join("config.toml"))
+}
+
+// fill args from the profile they name, or the config's default profile
+// a missing config file is only an error if a profile or a path was given
+pub fn resolve(args: Args) -> Result<Args, ConfigErr> {
+    let path = match args.config.clone().or_else(path) {
+        Some(path) => path,
+        None if args.profile.is_some() => return Err(ConfigErr::NotFoundErr),
+        None => return Ok(args),
+    };
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(ConfigErr::NotFoundErr) if args.config.is_none() && args.profile.is_none() => {
+            return Ok(args)
+        }
+        Err(err) => return Err(err),
+    };
+    match config.profile(args.profile.as_deref())? {
+        Some(profile) => Ok(args.or(profile.args()?)),
+        None => Ok(args),
+    }
+}
@@ -0,0 +1,132 @@
+use std::net::Ipv4Addr;
+
+use crate::{
+    bright::BrightMap,
+    colproc::{self, Band},
+    udp::{Cmd, CmdErr, Lamp, Light, Turn},
+};
+
+// per lamp options, maxb is the brightness ceiling and hue the rotation in degrees
+#[derive(Debug, Clone, Copy)]
+pub struct MemberOpts {
+    pub maxb: u8,
+    pub hue: f32,
+    pub band: Band,
+}
+
+impl Default for MemberOpts {
+    fn default() -> Self {
+        MemberOpts {
+            maxb: 100,
+            hue: 0.0,
+            band: Band::FULL,
+        }
+    }
+}
+
+// bright is the mapping levels from colproc go through, its max is held under maxb
+#[derive(Debug)]
+pub struct Member<L: Light = Lamp> {
+    pub lamp: L,
+    pub opts: MemberOpts,
+    pub bright: BrightMap,
+}
+
+impl<L: Light> Member<L> {
+    // brightness sent for a 0-100 level from colproc
+    pub fn brightness(&self, level: u8) -> u8 {
+        self.bright.map(level)
+    }
+
+    // color sent for one from colproc
+    pub fn color(&self, rgb: [u8; 3]) -> [u8; 3] {
+        colproc::rotate_hue(rgb, self.opts.hue)
+    }
+}
+
+// lamps driven together, each one gets its own frame from colproc::process_bands, frames
+// are sent by sched::Scheduler
+// any backend works, udp::Lamp over the lan or cloud::CloudLamp over the http api
+#[derive(Debug)]
+pub struct LampGroup<L: Light = Lamp> {
+    members: Vec<Member<L>>,
+}
+
+impl<L: Light> LampGroup<L> {
+    pub fn new() -> Self {
+        LampGroup {
+            members: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, lamp: L, opts: MemberOpts) {
+        let bright = BrightMap {
+            max: opts.maxb,
+            ..BrightMap::default()
+        };
+        self.members.push(Member { lamp, opts, bright });
+    }
+
+    pub fn members(&self) -> &[Member<L>] {
+        &self.members
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    // bands in member order, to be passed to colproc::process_bands
+    pub fn bands(&self) -> Vec<Band> {
+        self.members.iter().map(|m| m.opts.band).collect()
+    }
+
+    // point a member at the address its lamp moved to
+    pub fn set_ip(&mut self, index: usize, ip: Ipv4Addr) {
+        if let Some(member) = self.members.get_mut(index) {
+            member.lamp.set_ip(ip);
+        }
+    }
+
+    // set the brightness mapping of every member, its max is held under each member's own
+    // ceiling
+    pub fn set_bright(&mut self, bright: BrightMap) {
+        for member in self.members.iter_mut() {
+            member.bright = BrightMap {
+                max: bright.max.min(member.opts.maxb),
+                ..bright
+            };
+        }
+    }
+
+    // power every member on or off, the last error is returned
+    pub fn turn(&self, turn: Turn) -> Result<(), CmdErr> {
+        let mut res = Ok(());
+        for member in self.members.iter() {
+            if let Err(err) = member.lamp.send_cmd(Cmd::OnOff(turn)) {
+                res = Err(err);
+            }
+        }
+        res
+    }
+
+    // restore every member to its initial state, the last error is returned
+    pub fn restore(&self) -> Result<(), CmdErr> {
+        let mut res = Ok(());
+        for member in self.members.iter() {
+            if let Err(err) = member.lamp.restore() {
+                res = Err(err);
+            }
+        }
+        res
+    }
+}
+
+impl<L: Light> Default for LampGroup<L> {
+    fn default() -> Self {
+        LampGroup::new()
+    }
+}
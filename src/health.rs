@@ -0,0 +1,150 @@
+use std::{
+    net::Ipv4Addr,
+    sync::{mpsc::Sender, Arc, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::udp::{DiscoveryOptions, Lamp};
+
+// how often waits check whether to stop
+const POLL: Duration = Duration::from_millis(100);
+
+// connection to the lamps as seen by the heartbeat, shared with every thread
+// audio keeps running until Stopped, frames sent while the lamps are away are lost
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Connected,
+    // a lamp stopped answering, attempt counts from 1
+    Reconnecting { attempt: u32 },
+    // gave up reconnecting
+    Lost,
+    // shutting down
+    Stopped,
+}
+
+impl Status {
+    pub fn running(&self) -> bool {
+        !matches!(self, Status::Stopped)
+    }
+}
+
+// how often the lamps are checked and how reconnecting backs off, backoff doubles on
+// every failed attempt up to max_backoff, None retries forever
+#[derive(Debug, Clone)]
+pub struct HeartbeatOpts {
+    pub interval: Duration,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub retries: Option<u32>,
+    // used to look for lamps that moved
+    pub discovery: DiscoveryOptions,
+}
+
+impl Default for HeartbeatOpts {
+    fn default() -> Self {
+        HeartbeatOpts {
+            interval: Duration::from_secs(3),
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            retries: None,
+            discovery: DiscoveryOptions::default(),
+        }
+    }
+}
+
+// a lamp found at a new address, index is its place in the group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Moved {
+    pub index: usize,
+    pub ip: Ipv4Addr,
+}
+
+// set the status unless shutting down, false once stopped
+fn set(status: &RwLock<Status>, next: Status) -> bool {
+    let mut status = status.write().unwrap();
+    if status.running() {
+        *status = next;
+    }
+    status.running()
+}
+
+// sleep for dur, false if stopped in the meantime
+fn wait(status: &RwLock<Status>, dur: Duration) -> bool {
+    let deadline = Instant::now() + dur;
+    loop {
+        if !status.read().unwrap().running() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep(POLL.min(deadline - now));
+    }
+}
+
+// check every lamp each interval, while any is down retry with backoff and scan for
+// lamps with a known mac in case they got a new address, moves are sent on tx so the
+// lamps sending frames can follow
+// lamps are handles from Lamp::try_clone paired with their mac, empty if unknown
+pub fn heartbeat(
+    mut lamps: Vec<(Lamp, String)>,
+    status: Arc<RwLock<Status>>,
+    tx: Sender<Moved>,
+    opts: HeartbeatOpts,
+) {
+    loop {
+        if !wait(&status, opts.interval) {
+            return;
+        }
+        let mut attempt = 0;
+        let mut backoff = opts.backoff;
+        loop {
+            let down: Vec<usize> = lamps
+                .iter()
+                .enumerate()
+                .filter(|(_, (lamp, _))| lamp.check().is_err())
+                .map(|(i, _)| i)
+                .collect();
+            if down.is_empty() {
+                if !set(&status, Status::Connected) {
+                    return;
+                }
+                break;
+            }
+            if opts.retries.is_some_and(|retries| attempt >= retries) {
+                set(&status, Status::Lost);
+                return;
+            }
+            attempt += 1;
+            if !set(&status, Status::Reconnecting { attempt }) || !wait(&status, backoff) {
+                return;
+            }
+            backoff = (backoff * 2).min(opts.max_backoff);
+
+            if down.iter().all(|&i| lamps[i].1.is_empty()) {
+                continue;
+            }
+            let devices = match lamps[down[0]].0.scan(&opts.discovery) {
+                Ok(devices) => devices,
+                Err(_) => continue,
+            };
+            for i in down {
+                let (lamp, mac) = &mut lamps[i];
+                let found = devices
+                    .iter()
+                    .find(|info| !mac.is_empty() && info.mac == *mac);
+                if let Some(info) = found {
+                    if info.ip != *lamp.addr.ip() {
+                        lamp.addr.set_ip(info.ip);
+                        let _ = tx.send(Moved {
+                            index: i,
+                            ip: info.ip,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
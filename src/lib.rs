@@ -1,33 +1,112 @@
-use std::time::Duration;
-
 const WINDOW: usize = 4096;
+const HOP: usize = 512;
+// smaller windows leave too few bins for the bands, smaller hops call the tempo tracker
+// more often than it can keep up with
+const MIN_WINDOW: usize = 256;
+const MIN_HOP: usize = 256;
+const RATE: u32 = 44100;
 pub const CMDDELAY: usize = 46;
+pub const SCANTIMEOUT: usize = 2000;
 pub const BOLDSTART: &str = "\x1b[1m";
 pub const BOLDEND: &str = "\x1b[0m";
 
+pub mod agc;
+pub mod aio;
 pub mod audproc;
+pub mod beat;
+pub mod bright;
+pub mod cache;
+pub mod cli;
+pub mod cloud;
 pub mod colproc;
+pub mod config;
+pub mod group;
+pub mod health;
+pub mod protocol;
+pub mod sched;
+pub mod spectrum;
+pub mod synth;
 pub mod udp;
+pub mod wav;
+
+// analysis window and hop size in samples, each frame sent to colproc holds window
+// samples and starts hop samples after the previous one
+#[derive(Debug, Clone, Copy)]
+pub struct Frames {
+    pub window: usize,
+    pub hop: usize,
+}
+
+impl Frames {
+    // window must be a power of two of at least MIN_WINDOW and hop between MIN_HOP and window
+    pub fn new(window: usize, hop: usize) -> Option<Self> {
+        if window.is_power_of_two() && window >= MIN_WINDOW && hop >= MIN_HOP && hop <= window {
+            Some(Frames { window, hop })
+        } else {
+            None
+        }
+    }
+
+    // window of the given size with the default hop, or the window itself if smaller
+    pub fn with_window(window: usize) -> Option<Self> {
+        Frames::with(Some(window), None)
+    }
+
+    // window and hop with the defaults for whichever isn't given
+    pub fn with(window: Option<usize>, hop: Option<usize>) -> Option<Self> {
+        let window = window.unwrap_or(WINDOW);
+        Frames::new(window, hop.unwrap_or(HOP.min(window)))
+    }
+
+    // frames sent per second
+    pub fn fps(&self) -> f32 {
+        RATE as f32 / self.hop as f32
+    }
+}
+
+impl Default for Frames {
+    fn default() -> Self {
+        Frames {
+            window: WINDOW,
+            hop: HOP,
+        }
+    }
+}
+
+// process exit statuses, a signal exits with 128 plus its number like a shell would
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_CONFIG: i32 = 1;
+// lamps not found or stopped responding
+pub const EXIT_CONNECT: i32 = 3;
+pub const EXIT_AUDIO: i32 = 4;
+pub const EXIT_WAV: i32 = 5;
+pub const EXIT_INTERNAL: i32 = 6;
+// lamps could not be put back the way they were
+pub const EXIT_RESTORE: i32 = 7;
 
 // misc errors for audproc and colproc
+#[derive(Debug)]
 pub enum LampErr {
     PAErr,
     SendErr,
+    WavErr,
 }
 
-// catch method to call on any error received
 impl LampErr {
-    pub fn catch(&self) {
+    // print what went wrong and return the exit status for it, the caller restores the lamps
+    pub fn catch(&self) -> i32 {
         match self {
             Self::PAErr => {
                 println!("Unrecoverable Pulse Audio error, exiting...");
-                std::thread::sleep(Duration::from_secs(1));
-                std::process::exit(0);
+                EXIT_AUDIO
+            }
+            Self::WavErr => {
+                println!("Unable to read WAV file, exiting...");
+                EXIT_WAV
             }
             Self::SendErr => {
                 println!("Unrecoverable error sending data between threads, exiting...");
-                std::thread::sleep(Duration::from_secs(1));
-                std::process::exit(0);
+                EXIT_INTERNAL
             }
         }
     }
@@ -1,15 +1,36 @@
 use lamper::{
-    audproc, colproc,
-    udp::{self, InitErr, Lamp},
-    LampErr, {BOLDEND, BOLDSTART, CMDDELAY},
+    audproc,
+    cache::{self, LampCache},
+    cli::{parse_band, parse_beats, parse_colors, Args, Retry, Target, Verbosity},
+    cloud::{Client, CloudDevice, CloudLamp, CloudOpts},
+    colproc::{self, Band, Beats, Mode},
+    config,
+    group::{LampGroup, MemberOpts},
+    health::{self, HeartbeatOpts, Status},
+    sched::{Metrics, SchedOpts, Scheduler},
+    synth::{Signal, Synth},
+    udp::{self, CmdErr, DeviceInfo, DiscoveryOptions, InitErr, Lamp, Light, State},
+    wav::{Playback, Wav},
+    Frames, LampErr, {BOLDEND, BOLDSTART},
+    {EXIT_CONFIG, EXIT_CONNECT, EXIT_INTERNAL, EXIT_OK, EXIT_RESTORE},
+};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
 };
 use std::{
-    io::{self, Write},
-    sync::{mpsc, Arc, RwLock},
+    io::{self, IsTerminal, Write},
+    net::Ipv4Addr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, RwLock,
+    },
     thread,
     time::Duration,
 };
-use udp::{Cmd, Turn};
+use udp::Turn;
 
 // set the max brightness level
 fn max_brightness() -> u8 {
@@ -42,143 +63,1023 @@ fn max_brightness() -> u8 {
     }
 }
 
-fn connect() -> (Lamp, bool) {
-    let catch = |err: InitErr| -> bool {
-        match err {
-            InitErr::DevStatusErr => {
-                println!("Error retrieving device status, retry connection? [Y/n]");
-                match read_line() {
-                    Ok(val) => {
-                        if val.is_empty() || val == "y" || val == "Y" {
-                            true
-                        } else {
-                            !(val == "n" || val == "N")
+// pick the pulse audio source to capture from, defaults to the default sink's monitor
+fn audio_source() -> Option<String> {
+    let sources = match audproc::sources() {
+        Ok(sources) => sources,
+        Err(_) => {
+            println!("Failed to list audio sources, using server default");
+            return None;
+        }
+    };
+    if sources.is_empty() {
+        println!("No audio sources found, using server default");
+        return None;
+    }
+
+    println!("{}Audio sources:{}", BOLDSTART, BOLDEND);
+    for (i, source) in sources.iter().enumerate() {
+        let kind = if source.monitor { "monitor" } else { "input" };
+        let default = if source.default { " (default)" } else { "" };
+        println!("{}: {} [{}]{}", i + 1, source.description, kind, default);
+    }
+    let default = sources.iter().position(|source| source.default);
+    match default {
+        Some(i) => print!(
+            "{}Select audio source(1-{}){} [{}]: ",
+            BOLDSTART,
+            sources.len(),
+            BOLDEND,
+            i + 1
+        ),
+        None => print!(
+            "{}Select audio source(1-{}){} [server default]: ",
+            BOLDSTART,
+            sources.len(),
+            BOLDEND
+        ),
+    }
+    flush();
+    loop {
+        match read_line() {
+            Ok(val) => {
+                if val.is_empty() {
+                    return default.map(|i| sources[i].name.clone());
+                } else if let Ok(num) = val.trim().parse::<usize>() {
+                    if num <= sources.len() && num > 0 {
+                        return Some(sources[num - 1].name.clone());
+                    }
+                }
+                print!(
+                    "Please enter a value 1-{} or press enter for default: ",
+                    sources.len()
+                );
+                flush();
+            }
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+            }
+        }
+    }
+}
+
+// where the audio comes from, a pulse audio source by name or the server default, a wav
+// file or a test signal
+enum Audio {
+    Pulse(Option<String>),
+    Wav(PathBuf),
+    Synth(Signal),
+}
+
+// amplitude of --synth signals
+const SYNTH_AMPLITUDE: f32 = 0.5;
+
+// default sink's monitor without prompting, falls back to the server default
+fn default_source() -> Option<String> {
+    match audproc::default_monitor() {
+        Ok(source) => source.map(|source| source.name),
+        Err(_) => None,
+    }
+}
+
+// pick one or more devices from the ones that answered the scan or are on the account
+// describe is the line each one is listed with
+fn select_devices<T: Clone>(devices: &[T], describe: fn(&T) -> String) -> Vec<T> {
+    println!("{}Govee devices found:{}", BOLDSTART, BOLDEND);
+    for (i, info) in devices.iter().enumerate() {
+        println!("{}: {}", i + 1, describe(info));
+    }
+    if devices.len() == 1 {
+        return devices.to_vec();
+    }
+    print!(
+        "{}Select device(s)(1-{}, comma separated or \"all\"){} [1]: ",
+        BOLDSTART,
+        devices.len(),
+        BOLDEND
+    );
+    flush();
+    loop {
+        match read_line() {
+            Ok(val) => {
+                if val.is_empty() {
+                    return vec![devices[0].clone()];
+                } else if val == "all" {
+                    return devices.to_vec();
+                }
+                let picks: Option<Vec<T>> = val
+                    .split(',')
+                    .map(|num| match num.trim().parse::<usize>() {
+                        Ok(num) if num <= devices.len() && num > 0 => {
+                            Some(devices[num - 1].clone())
                         }
+                        _ => None,
+                    })
+                    .collect();
+                if let Some(picks) = picks {
+                    return picks;
+                }
+                print!(
+                    "Please enter values 1-{}, \"all\" or press enter for default [1]: ",
+                    devices.len()
+                );
+                flush();
+            }
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+            }
+        }
+    }
+}
+
+// frequency band, hue rotation and brightness ceiling for one lamp of a group, name is
+// how the lamp is shown in the prompts
+fn member_opts(name: &str, range: Band) -> MemberOpts {
+    let mut opts = MemberOpts {
+        band: range,
+        ..MemberOpts::default()
+    };
+    print!(
+        "{}Band for {}(full/bass/mid/treble){} [full]: ",
+        BOLDSTART, name, BOLDEND
+    );
+    flush();
+    loop {
+        match read_line() {
+            Ok(val) => {
+                opts.band = match val.as_str() {
+                    "" | "full" => range,
+                    "bass" => Band::BASS,
+                    "mid" => Band::MID,
+                    "treble" => Band::TREBLE,
+                    _ => {
+                        print!("Please enter full, bass, mid or treble [full]: ");
+                        flush();
+                        continue;
+                    }
+                };
+                break;
+            }
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+            }
+        }
+    }
+    print!(
+        "{}Hue rotation for {}(0-359){} [0]: ",
+        BOLDSTART, name, BOLDEND
+    );
+    flush();
+    loop {
+        match read_line() {
+            Ok(val) => {
+                if val.is_empty() {
+                    break;
+                } else if let Ok(num) = val.parse::<u16>() {
+                    if num < 360 {
+                        opts.hue = num as f32;
+                        break;
                     }
-                    Err(err) => {
-                        println!("Failed to read line: {}", err);
-                        false
+                }
+                print!("Please enter a value 0-359 or press enter for default [0]: ");
+                flush();
+            }
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+            }
+        }
+    }
+    print!(
+        "{}Brightness ceiling for {}(1-100){} [100]: ",
+        BOLDSTART, name, BOLDEND
+    );
+    flush();
+    loop {
+        match read_line() {
+            Ok(val) => {
+                if val.is_empty() {
+                    break;
+                } else if let Ok(num) = val.parse::<u8>() {
+                    if num <= 100 && num > 0 {
+                        opts.maxb = num;
+                        break;
                     }
                 }
+                print!("Please enter a value 1-100 or press enter for default [100]: ");
+                flush();
+            }
+            Err(err) => {
+                println!("Failed to read line: {}", err);
             }
-            _ => false,
         }
+    }
+    opts
+}
+
+// retry policy and output level for the session, resolved from the args
+struct Session {
+    interactive: bool,
+    retry: Retry,
+    verbosity: Verbosity,
+}
+
+impl Session {
+    fn new(args: &Args) -> Self {
+        // prompts need someone at the terminal
+        let interactive = io::stdin().is_terminal();
+        let retry = match args.retry {
+            Some(retry) => retry,
+            None if interactive => Retry::Ask,
+            None => Retry::Always,
+        };
+        Session {
+            interactive,
+            retry,
+            verbosity: args.verbosity,
+        }
+    }
+
+    // informational output, hidden by --quiet
+    fn info(&self) -> bool {
+        self.verbosity >= Verbosity::Normal
+    }
+
+    fn verbose(&self) -> bool {
+        self.verbosity >= Verbosity::Verbose
+    }
+
+    // whether to retry after attempts failures in a row, None to ask
+    fn retry(&self, attempts: u32) -> Option<bool> {
+        match self.retry {
+            Retry::Ask if self.interactive => None,
+            Retry::Ask | Retry::Always => Some(true),
+            Retry::Never => Some(false),
+            Retry::Times(times) => Some(attempts <= times),
+        }
+    }
+
+    // reconnect attempts once running, None for no limit
+    // reconnecting happens in the background so there is no one to ask, q still quits
+    fn retries(&self) -> Option<u32> {
+        match self.retry {
+            Retry::Ask | Retry::Always => None,
+            Retry::Never => Some(0),
+            Retry::Times(times) => Some(times),
+        }
+    }
+}
+
+// answer to a Y/n prompt, yes on empty input
+fn yes(val: &str) -> Option<bool> {
+    match val {
+        "" | "y" | "Y" => Some(true),
+        "n" | "N" => Some(false),
+        _ => None,
+    }
+}
+
+// wait before the first retry that isn't asked about, doubled after every failure in a
+// row up to MAX_RETRY_WAIT
+const RETRY_WAIT: Duration = Duration::from_millis(500);
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(30);
+
+// whether to try connecting again after attempts failures in a row, msg says what failed
+// retries nobody is asked about back off so errors that fail straight away don't spin
+fn again(session: &Session, attempts: u32, msg: &str) -> bool {
+    println!("{}", msg);
+    if let Some(retry) = session.retry(attempts) {
+        if retry {
+            let wait = RETRY_WAIT.saturating_mul(1 << attempts.saturating_sub(1).min(16));
+            thread::sleep(wait.min(MAX_RETRY_WAIT));
+        }
+        return retry;
+    }
+    println!("Retry? [Y/n]");
+    match read_line() {
+        Ok(val) => yes(&val).unwrap_or(true),
+        Err(err) => {
+            println!("Failed to read line: {}", err);
+            false
+        }
+    }
+}
+
+// give up on connecting, there is nothing to restore yet
+fn fail() -> ! {
+    println!("Unrecoverable error, exiting...");
+    std::thread::sleep(Duration::from_secs(2));
+    std::process::exit(EXIT_CONNECT);
+}
+
+fn connect(
+    session: &Session,
+    args: &Args,
+    range: Band,
+    opts: &DiscoveryOptions,
+) -> (LampGroup, Vec<DeviceInfo>) {
+    let mut attempts = 0;
+    let mut retry = |msg: &str| -> bool {
+        attempts += 1;
+        again(session, attempts, msg)
     };
+    let mut catch = |err: InitErr| -> bool {
+        match err {
+            InitErr::DevStatusErr(_) => retry("Error retrieving device status"),
+            InitErr::NotFoundErr => retry("No matching Govee devices found"),
+            // the send fails at once while the network is down, wait as long as a scan
+            // that went out and heard nothing would have
+            InitErr::ScanErr(_) => {
+                thread::sleep(opts.timeout);
+                retry("Failed to send scan, is the network up?")
+            }
+            InitErr::BindErr { addr, .. } => {
+                println!("Failed to bind {}, is lamper already running?", addr);
+                false
+            }
+            err => {
+                println!("{:?}", err);
+                false
+            }
+        }
+    };
+
+    // lamps seen by earlier scans, used for lamps asked for by mac that the scan missed
+    let cache_path = cache::path();
+    let mut cache = cache_path
+        .as_deref()
+        .map(LampCache::load)
+        .unwrap_or_default();
+    // lamps given by ip alone are connected to without a scan
+    let targets = &args.devices;
+    let scan = targets.is_empty() || targets.iter().any(|t| !matches!(t, Target::Ip(_)));
 
-    // establish connection with device
+    // establish connection with devices
     loop {
-        println!(
-            "{}Searching for Govee device on current network...{}",
-            BOLDSTART, BOLDEND
-        );
-        let res = match udp::init() {
-            Ok(lamp) => Some(lamp),
-            Err(err) => match catch(err) {
-                true => {
-                    continue;
+        let devices = if scan {
+            if session.info() {
+                println!(
+                    "{}Searching for Govee devices on current network...{}",
+                    BOLDSTART, BOLDEND
+                );
+            }
+            match udp::discover_with(opts) {
+                Ok(devices) => devices,
+                Err(err) => match catch(err) {
+                    true => continue,
+                    false => fail(),
+                },
+            }
+        } else {
+            Vec::new()
+        };
+        if !devices.is_empty() {
+            for info in devices.iter() {
+                cache.insert(info);
+            }
+            if let Some(path) = &cache_path {
+                if let Err(err) = cache.save(path) {
+                    println!("Failed to save lamp cache: {}", err);
                 }
-                false => {
-                    println!("Unrecoverable error, exiting...");
-                    std::thread::sleep(Duration::from_secs(2));
-                    std::process::exit(0);
+            }
+        }
+        let picks = match pick_devices(session, targets, &devices, &cache) {
+            Some(picks) => picks,
+            None => match catch(InitErr::NotFoundErr) {
+                true => continue,
+                false => fail(),
+            },
+        };
+        let lamps = match udp::connect_all_with(&picks, opts) {
+            Ok(lamps) => lamps,
+            Err(err) => match catch(err) {
+                true => continue,
+                false => fail(),
+            },
+        };
+
+        let mut group = LampGroup::new();
+        for (info, lamp) in picks.iter().zip(lamps) {
+            if session.info() {
+                line();
+                println!("{}Connected to {}:{}", BOLDSTART, info.sku, BOLDEND);
+                println!("{}IP:{} {}", BOLDSTART, BOLDEND, &lamp.addr);
+                show_init(&lamp.init);
+            }
+
+            // lamps set up in the profile aren't asked about
+            let opts = match args.lamp(&info.ip, &info.mac) {
+                Some(lamp) => lamp.member(range),
+                None if picks.len() > 1 && session.interactive => {
+                    member_opts(&format!("{} {}", info.sku, info.ip), range)
                 }
+                None => MemberOpts {
+                    band: range,
+                    ..MemberOpts::default()
+                },
+            };
+            group.add(lamp, opts);
+        }
+        return (group, picks);
+    }
+}
+
+// print the state a lamp was in when connected, what it is restored to
+fn show_init(init: &State) {
+    let pwr = match init.pwr {
+        Turn::On => "On",
+        Turn::Off => "Off",
+    };
+    let [r, g, b] = init.color;
+    println!(
+        "{}Initial State:{}\nPower: {}\nBrightness: {}\nColor(RGB): {}, {}, {}\nColor(Kelvin): {}",
+        BOLDSTART, BOLDEND, pwr, init.bright, r, g, b, init.temp
+    );
+}
+
+// least time between listings of the devices on the account
+const LIST_WAIT: Duration = Duration::from_secs(10);
+
+// lamps on the govee account, driven through the cloud api instead of the lan
+// devices named by mac or device id are picked, otherwise prompt or take the first one
+fn connect_cloud(
+    session: &Session,
+    args: &Args,
+    key: &str,
+    range: Band,
+) -> (LampGroup<CloudLamp>, SchedOpts) {
+    // the api only knows devices by id, an ip would never match one
+    if let Some(Target::Ip(ip)) = args.devices.iter().find(|t| matches!(t, Target::Ip(_))) {
+        println!(
+            "Cloud devices are named by MAC or device ID, not by IP address ({})",
+            ip
+        );
+        std::process::exit(EXIT_CONFIG);
+    }
+    let opts = CloudOpts::default();
+    let rate = SchedOpts::per_minute(opts.per_minute);
+    let client = match Client::with_opts(key, opts) {
+        Ok(client) => client,
+        Err(err) => {
+            println!("Failed to start the API client: {:?}", err);
+            fail();
+        }
+    };
+    let mut attempts = 0;
+    // out of quota is waited out before trying again, the client refuses until then
+    let mut catch = |msg: &str, err: Option<CmdErr>| -> bool {
+        attempts += 1;
+        let retry = match &err {
+            Some(err) => again(session, attempts, &format!("{}: {:?}", msg, err)),
+            None => again(session, attempts, msg),
+        };
+        if let (true, Some(CmdErr::RateLimitErr(wait))) = (retry, err) {
+            if session.info() {
+                println!("Waiting {}s for the API quota", wait.as_secs());
+            }
+            thread::sleep(wait);
+        }
+        retry
+    };
+
+    // every listing counts against the account's daily quota, so they are spaced out
+    let mut listed = false;
+    loop {
+        if listed {
+            thread::sleep(LIST_WAIT);
+        }
+        listed = true;
+        if session.info() {
+            println!(
+                "{}Listing Govee devices on the account...{}",
+                BOLDSTART, BOLDEND
+            );
+        }
+        let devices: Vec<CloudDevice> = match client.devices() {
+            Ok(devices) => devices
+                .into_iter()
+                .filter(|info| info.controllable)
+                .collect(),
+            Err(err) => match catch("Failed to list devices", Some(err)) {
+                true => continue,
+                false => fail(),
+            },
+        };
+        let picks = match pick_cloud(session, &args.devices, &devices) {
+            Some(picks) => picks,
+            None => match catch("No matching Govee devices found", None) {
+                true => continue,
+                false => fail(),
             },
         };
-        if let Some(ref lamp) = res {
-            println!("{}Govee device found:{}", BOLDSTART, BOLDEND);
-            println!("{}IP:{} {}", BOLDSTART, BOLDEND, &lamp.addr);
-            let pwr = match &lamp.init.pwr {
-                Turn::On => "On",
-                Turn::Off => "Off",
+        let lamps = match picks
+            .iter()
+            .map(|info| client.connect(info))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(lamps) => lamps,
+            Err(InitErr::DevStatusErr(err)) => {
+                match catch("Error retrieving device status", Some(err)) {
+                    true => continue,
+                    false => fail(),
+                }
+            }
+            Err(err) => {
+                println!("{:?}", err);
+                fail();
+            }
+        };
+
+        let mut group = LampGroup::new();
+        for lamp in lamps {
+            let name = format!("{} {}", lamp.info.model, lamp.info.device_name);
+            if session.info() {
+                line();
+                println!("{}Connected to {}:{}", BOLDSTART, name, BOLDEND);
+                println!("{}Device:{} {}", BOLDSTART, BOLDEND, lamp.info.device);
+                show_init(&lamp.init);
+            }
+            let opts = match args.lamp(&Ipv4Addr::UNSPECIFIED, &lamp.info.mac()) {
+                Some(lamp) => lamp.member(range),
+                None if picks.len() > 1 && session.interactive => member_opts(&name, range),
+                None => MemberOpts {
+                    band: range,
+                    ..MemberOpts::default()
+                },
             };
-            let r = &lamp.init.color[0];
-            let g = &lamp.init.color[1];
-            let b = &lamp.init.color[2];
-            println!("{}Initial State:{}\nPower: {}\nBrightness: {}\nColor(RGB): {}, {}, {}\nColor(Kelvin): {}", BOLDSTART, BOLDEND, pwr, &lamp.init.bright, r, g, b, &lamp.init.temp);
+            group.add(lamp, opts);
+        }
+        return (group, rate);
+    }
+}
+
+// devices on the account named by mac or device id, otherwise prompt or take the first
+// one, None if a named device isn't on the account
+fn pick_cloud(
+    session: &Session,
+    targets: &[Target],
+    devices: &[CloudDevice],
+) -> Option<Vec<CloudDevice>> {
+    if devices.is_empty() {
+        return None;
+    } else if targets.contains(&Target::All) {
+        return Some(devices.to_vec());
+    } else if targets.is_empty() && session.interactive {
+        return Some(select_devices(devices, |info| {
+            format!(
+                "{} {} (device: {})",
+                info.model, info.device_name, info.device
+            )
+        }));
+    } else if targets.is_empty() {
+        return Some(vec![devices[0].clone()]);
+    }
+    targets
+        .iter()
+        .map(|target| {
+            devices
+                .iter()
+                .find(|info| {
+                    matches!(target, Target::Mac(_))
+                        && target.matches(&Ipv4Addr::UNSPECIFIED, &info.mac())
+                })
+                .cloned()
+        })
+        .collect()
+}
+
+// devices named on the command line, otherwise prompt or take the first one
+// lamps the scan missed come from the cache or are connected to by ip alone
+// None if a named device can't be found at all
+fn pick_devices(
+    session: &Session,
+    targets: &[Target],
+    devices: &[DeviceInfo],
+    cache: &LampCache,
+) -> Option<Vec<DeviceInfo>> {
+    if targets.is_empty() || targets.contains(&Target::All) {
+        if devices.is_empty() {
+            return None;
+        } else if !targets.is_empty() {
+            return Some(devices.to_vec());
+        } else if session.interactive {
+            return Some(select_devices(devices, |info| {
+                format!(
+                    "{} {} (MAC: {}, firmware: {})",
+                    info.sku, info.ip, info.mac, info.firmware.wifi_soft
+                )
+            }));
         }
-        let res = res.unwrap();
-        if let Err(err) = res.send_cmd(Cmd::OnOff(Turn::On)) {
-            println!("Failed to turn lamp on: {:?}", err);
+        return Some(vec![devices[0].clone()]);
+    }
+    targets
+        .iter()
+        .map(|target| {
+            let scanned = devices
+                .iter()
+                .find(|info| target.matches(&info.ip, &info.mac));
+            if let Some(info) = scanned {
+                return Some(info.clone());
+            }
+            match target {
+                Target::Ip(ip) => Some(
+                    cache
+                        .find_ip(*ip)
+                        .cloned()
+                        .unwrap_or_else(|| DeviceInfo::from_ip(*ip)),
+                ),
+                Target::Mac(mac) => {
+                    let info = cache.get(mac)?;
+                    if session.info() {
+                        println!("{} not found, trying last known address {}", mac, info.ip);
+                    }
+                    Some(info.clone())
+                }
+                Target::All => None,
+            }
+        })
+        .collect()
+}
+
+// default brightness mode, red following the kick drum
+fn brightness_mode() -> Mode {
+    Mode::Brightness {
+        band: Band::KICK,
+        colors: vec![[255, 0, 0]],
+    }
+}
+
+// default cycle mode, a new color from the default palette every four beats
+fn cycle_mode() -> Mode {
+    Mode::Cycle {
+        beats: Beats::Four,
+        palette: colproc::PALETTE.to_vec(),
+    }
+}
+
+// default beat mode, flashes through the default palette
+fn beat_mode() -> Mode {
+    Mode::Beat {
+        palette: colproc::PALETTE.to_vec(),
+    }
+}
+
+// choose between hz -> color, hz -> brightness, beat synced color cycling and onset flashes
+fn select_mode() -> Mode {
+    print!(
+        "{}Select mode(spectrum/brightness/cycle/beat){} [spectrum]: ",
+        BOLDSTART, BOLDEND
+    );
+    flush();
+    loop {
+        match read_line() {
+            Ok(val) => match val.as_str() {
+                "" | "spectrum" => return Mode::Spectrum,
+                "brightness" => return select_brightness(),
+                "cycle" => return select_cycle(),
+                "beat" => return select_beat(),
+                _ => {
+                    print!("Please enter spectrum, brightness, cycle or beat [spectrum]: ");
+                    flush();
+                }
+            },
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+            }
         }
-        return (res, true);
     }
 }
 
-fn run(conn: Arc<RwLock<bool>>, lamp: Lamp) {
-    // conn atomics
-    let apconn = Arc::clone(&conn);
-    let cpconn = Arc::clone(&conn);
+// band and colors for brightness mode
+fn select_brightness() -> Mode {
+    print!(
+        "{}Band(full/kick/bass/mid/treble or min-max hz){} [kick]: ",
+        BOLDSTART, BOLDEND
+    );
+    flush();
+    let band = loop {
+        match read_line() {
+            Ok(val) if val.is_empty() => break Band::KICK,
+            Ok(val) => match parse_band(&val) {
+                Some(band) => break band,
+                None => {
+                    print!("Please enter a band name or a range like 40-120 [kick]: ");
+                    flush();
+                }
+            },
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+            }
+        }
+    };
+
+    print!(
+        "{}Color or palette(r,g,b;r,g,b...){} [255,0,0]: ",
+        BOLDSTART, BOLDEND
+    );
+    flush();
+    let colors = select_colors().unwrap_or_else(|| vec![[255, 0, 0]]);
+
+    Mode::Brightness { band, colors }
+}
+
+// beats per color and palette for cycle mode
+fn select_cycle() -> Mode {
+    print!("{}Beats per color(1/2/4/8){} [4]: ", BOLDSTART, BOLDEND);
+    flush();
+    let beats = loop {
+        match read_line() {
+            Ok(val) if val.is_empty() => break Beats::Four,
+            Ok(val) => match parse_beats(&val) {
+                Some(beats) => break beats,
+                None => {
+                    print!("Please enter 1, 2, 4 or 8 [4]: ");
+                    flush();
+                }
+            },
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+            }
+        }
+    };
+
+    print!(
+        "{}Palette(r,g,b;r,g,b...){} [default]: ",
+        BOLDSTART, BOLDEND
+    );
+    flush();
+    let palette = select_colors().unwrap_or_else(|| colproc::PALETTE.to_vec());
+
+    Mode::Cycle { beats, palette }
+}
+
+// palette for beat mode
+fn select_beat() -> Mode {
+    print!(
+        "{}Palette(r,g,b;r,g,b...){} [default]: ",
+        BOLDSTART, BOLDEND
+    );
+    flush();
+    let palette = select_colors().unwrap_or_else(|| colproc::PALETTE.to_vec());
+
+    Mode::Beat { palette }
+}
+
+// read colors until they parse, None on empty input for the default
+fn select_colors() -> Option<Vec<[u8; 3]>> {
+    loop {
+        match read_line() {
+            Ok(val) if val.is_empty() => return None,
+            Ok(val) => match parse_colors(&val) {
+                Some(colors) => return Some(colors),
+                None => {
+                    print!("Please enter colors like 255,0,0;0,0,255: ");
+                    flush();
+                }
+            },
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+            }
+        }
+    }
+}
+
+// read stdin on its own thread so the run loop can poll it for commands
+fn input() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut input = String::new();
+        match io::stdin().read_line(&mut input) {
+            Ok(0) => break,
+            Ok(_) => {
+                if tx.send(input.trim().to_string()).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                println!("Failed to read line: {}", err);
+                break;
+            }
+        }
+    });
+    rx
+}
+
+// drive the lamps until told to stop, returns the exit status
+// lamps are handles to the group's lan lamps in order with their macs, checked by the
+// heartbeat and used to find lamps that moved, cloud lamps have none
+fn run<L: Light>(
+    session: &Session,
+    sched: &mut Scheduler<L>,
+    lamps: Vec<(Lamp, String)>,
+    opts: HeartbeatOpts,
+    audio: Audio,
+    mode: Mode,
+    frames: Frames,
+) -> i32 {
+    let status = Arc::new(RwLock::new(Status::Connected));
+    let apstatus = Arc::clone(&status);
+    let cpstatus = Arc::clone(&status);
+    let hbstatus = Arc::clone(&status);
+    // mode is switched from stdin, s for spectrum, b for brightness, c for cycle and o for
+    // beat (onset) mode
+    // the selected mode is kept as the preset for its key
+    let brightness = match mode {
+        Mode::Brightness { .. } => mode.clone(),
+        _ => brightness_mode(),
+    };
+    let cycle = match mode {
+        Mode::Cycle { .. } => mode.clone(),
+        _ => cycle_mode(),
+    };
+    let beat = match mode {
+        Mode::Beat { .. } => mode.clone(),
+        _ => beat_mode(),
+    };
+    let mode = Arc::new(RwLock::new(mode));
+    let cpmode = Arc::clone(&mode);
+    let lines = input();
+    if session.interactive && session.info() {
+        println!(
+            "{}Enter s for spectrum, b for brightness, c for cycle, o for beat mode or q to quit{}",
+            BOLDSTART, BOLDEND
+        );
+    }
     // channels
     let (aptx, aprx) = mpsc::channel();
     let (cptx, cprx) = mpsc::channel();
+    let (hbtx, hbrx) = mpsc::channel();
 
     // threads
-    let ap = thread::spawn(|| match audproc::start(aptx, apconn) {
-        Ok(_) => {}
-        Err(err) => err.catch(),
+    let ap = thread::spawn(move || match audio {
+        Audio::Pulse(source) => audproc::Pulse::new(source.as_deref())
+            .and_then(|pulse| audproc::start(aptx, apstatus, pulse, frames)),
+        Audio::Wav(path) => Wav::open(path, Playback::RealTime)
+            .and_then(|wav| audproc::start(aptx, apstatus, wav, frames)),
+        Audio::Synth(signal) => {
+            let synth = Synth::new(signal, SYNTH_AMPLITUDE);
+            audproc::start(aptx, apstatus, synth, frames)
+        }
     });
 
-    let cp = thread::spawn(|| match colproc::process(aprx, cptx, cpconn) {
-        Ok(_) => {}
-        Err(err) => err.catch(),
-    });
+    let bands = sched.group().bands();
+    let cp =
+        thread::spawn(move || colproc::process_bands(aprx, cptx, cpstatus, cpmode, bands, frames));
+
+    let macs: Vec<String> = lamps.iter().map(|(_, mac)| mac.clone()).collect();
+    let hb = thread::spawn(move || health::heartbeat(lamps, hbstatus, hbtx, opts));
 
-    let mut check: u8 = 0;
+    // from here on the lamps have been changed, signals wind down and restore them
+    ARMED.store(true, Ordering::SeqCst);
+    if let Err(err) = sched.group().turn(Turn::On) {
+        println!("Failed to turn lamp on: {:?}", err);
+    }
+
+    let mut code = EXIT_OK;
+    let mut last = Status::Connected;
     loop {
-        match cprx.recv() {
-            Ok(val) => {
-                if check < 255 {
-                    let (brightness, rgb) = val;
-                    match lamp.send_cmd(Cmd::Brightness(brightness)) {
-                        Ok(_) => {}
-                        Err(err) => println!("Error sending brightness: {:?}", err),
-                    }
-                    thread::sleep(Duration::from_millis(CMDDELAY as u64));
-                    match lamp.send_cmd(Cmd::Color(rgb)) {
-                        Ok(_) => {}
-                        Err(err) => println!("Error sending color: {:?}", err),
+        if let Some(signal) = signal() {
+            if session.info() {
+                println!("Received signal {}, restoring lamps...", signal);
+            }
+            code = 128 + signal;
+            break;
+        }
+
+        for moved in hbrx.try_iter() {
+            if session.info() {
+                println!("{} moved to {}", macs[moved.index], moved.ip);
+            }
+            sched.set_ip(moved.index, moved.ip);
+        }
+        let now = *status.read().unwrap();
+        if now != last {
+            match now {
+                Status::Connected => {
+                    if session.info() {
+                        println!("Reconnected");
                     }
-                    check += 1;
-                } else {
-                    loop {
-                        match lamp.check() {
-                            Ok(_) => {
-                                check = 0;
-                                *conn.write().unwrap() = true;
-                                break;
-                            }
-                            Err(_) => {
-                                *conn.write().unwrap() = false;
-                                println!("No response from device, retry connection? [Y/n]");
-                                match read_line() {
-                                    Ok(val) => {
-                                        if val.is_empty() || val == "y" || val == "Y" {
-                                            continue;
-                                        } else if val == "n" || val == "N" {
-                                            break;
-                                        }
-                                    }
-                                    Err(err) => {
-                                        println!("Failed to read line: {}", err);
-                                        continue;
-                                    }
-                                }
-                            }
-                        }
-                        thread::sleep(Duration::from_millis(CMDDELAY as u64));
+                    // the lamp may have lost power while away
+                    if let Err(err) = sched.group().turn(Turn::On) {
+                        println!("Failed to turn lamp on: {:?}", err);
                     }
-                    if !*conn.read().unwrap() {
-                        break;
+                    sched.forget();
+                }
+                Status::Reconnecting { attempt } => {
+                    if session.info() {
+                        println!(
+                            "No response from device(s), reconnecting (attempt {})",
+                            attempt
+                        );
                     }
                 }
+                Status::Lost => {
+                    println!("Lost connection to device(s)");
+                    code = EXIT_CONNECT;
+                    break;
+                }
+                Status::Stopped => {}
+            }
+            last = now;
+        }
+
+        // frames can arrive faster than the lamps take commands, the scheduler keeps only
+        // the newest values and sends them when the rate allows, while reconnecting they
+        // wait and the newest go out once the lamps are back
+        // the timeout keeps signals from waiting on a stalled audio thread
+        let wait = match sched.wait() {
+            Some(wait) if last == Status::Connected => wait.min(POLL),
+            _ => POLL,
+        };
+        match cprx.recv_timeout(wait) {
+            Ok(val) => {
+                sched.push(val);
+                for val in cprx.try_iter() {
+                    sched.push(val);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            // colproc is gone, its error or the audio error behind it is picked up below
+            Err(RecvTimeoutError::Disconnected) => {
+                code = EXIT_INTERNAL;
+                break;
+            }
+        }
+        if last == Status::Connected {
+            if let Err(err) = sched.flush() {
+                println!("Error sending frame: {:?}", err);
+            }
+        }
+
+        let mut quit = false;
+        for line in lines.try_iter() {
+            let next = match line.as_str() {
+                "q" => {
+                    quit = true;
+                    continue;
+                }
+                "s" => Mode::Spectrum,
+                "b" => brightness.clone(),
+                "c" => cycle.clone(),
+                "o" => beat.clone(),
+                _ => {
+                    println!("Unknown command: {}", line);
+                    continue;
+                }
+            };
+            if session.verbose() {
+                println!("Switching to {:?}", next);
             }
-            Err(err) => LampErr::from(err).catch(),
+            *mode.write().unwrap() = next;
         }
+        if quit {
+            break;
+        }
+    }
+
+    if session.verbose() {
+        println!("{}", metrics(sched.metrics()));
+    }
+    // stop every thread, dropping the receiver unblocks colproc if it is mid send
+    // the heartbeat is waited for so it isn't reading replies while lamps are restored
+    *status.write().unwrap() = Status::Stopped;
+    drop(cprx);
+    if hb.join().is_err() {
+        println!("Status check thread panicked");
+    }
+    let ap = ap.join();
+    // a wav file that played to the end closes the channel behind it
+    if code == EXIT_INTERNAL && matches!(ap, Ok(Ok(_))) {
+        if session.info() {
+            println!("End of audio input, restoring lamps...");
+        }
+        code = EXIT_OK;
     }
+    let errs: Vec<LampErr> = [ap, cp.join()]
+        .into_iter()
+        .filter_map(|res| match res {
+            Ok(res) => res.err(),
+            Err(_) => Some(LampErr::SendErr),
+        })
+        .collect();
+    // a closed channel is only the cause if nothing else went wrong
+    if code == EXIT_INTERNAL {
+        let err = errs
+            .iter()
+            .find(|err| !matches!(err, LampErr::SendErr))
+            .or(errs.first());
+        if let Some(err) = err {
+            code = err.catch();
+        }
+    }
+    code
+}
 
-    ap.join().unwrap();
-    cp.join().unwrap();
-    exit(lamp);
+// scheduler counters for verbose output
+fn metrics(metrics: Metrics) -> String {
+    format!(
+        "{} frames, {} commands sent, {} merged, {} dropped, {} unchanged",
+        metrics.frames, metrics.sent, metrics.merged, metrics.dropped, metrics.skipped
+    )
 }
 
 // clear terminal
@@ -204,19 +1105,165 @@ fn read_line() -> io::Result<String> {
     Ok(input.trim().to_string())
 }
 
-fn exit(lamp: Lamp) {
+// set once the lamps have been changed, signals before that exit straight away
+static ARMED: AtomicBool = AtomicBool::new(false);
+// last signal received, 0 for none
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+// how often blocking waits check for a signal
+const POLL: Duration = Duration::from_millis(100);
+
+// SIGINT and SIGTERM ask run to stop and restore the lamps, a second one exits without
+// restoring in case the lamps stopped answering
+fn handle_signals() {
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            println!("Failed to register signal handlers: {}", err);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if !ARMED.load(Ordering::SeqCst) || SIGNAL.swap(signal, Ordering::SeqCst) != 0 {
+                std::process::exit(128 + signal);
+            }
+        }
+    });
+}
+
+fn signal() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+// handles for the heartbeat's status checks, in group order with their macs
+// they share the group's socket since every reply comes back on the one reply port, the
+// main loop only sends while the heartbeat runs, so the heartbeat is the only reader
+// until it is stopped before the lamps are restored
+fn status_lamps(group: &LampGroup, devices: &[DeviceInfo]) -> Vec<(Lamp, String)> {
+    match group
+        .members()
+        .iter()
+        .zip(devices)
+        .map(|(member, info)| Ok((member.lamp.try_clone()?, info.mac.clone())))
+        .collect::<Result<Vec<_>, InitErr>>()
+    {
+        Ok(lamps) => lamps,
+        Err(err) => {
+            println!("Failed to start status checks: {:?}", err);
+            Vec::new()
+        }
+    }
+}
+
+// restore every lamp and exit with the given status
+fn exit<L: Light>(group: &LampGroup<L>, code: i32) -> ! {
     std::thread::sleep(Duration::from_secs(2));
-    lamp.restore()
-        .expect("Could not restore lamp to initial settings");
-    std::process::exit(0);
+    match group.restore() {
+        Ok(_) => std::process::exit(code),
+        Err(err) => {
+            println!("Could not restore lamp to initial settings: {:?}", err);
+            std::process::exit(if code == EXIT_OK { EXIT_RESTORE } else { code });
+        }
+    }
 }
 
 fn main() {
-    clear();
-    let (mut lamp, conn) = connect();
-    let conn = Arc::new(RwLock::new(conn));
-    line();
-    lamp.set_maxb(max_brightness());
-    line();
-    run(conn, lamp);
+    let args = match config::resolve(Args::parse()) {
+        Ok(args) => args,
+        Err(err) => {
+            println!("Failed to load config: {:?}", err);
+            std::process::exit(EXIT_CONFIG);
+        }
+    };
+    // window and hop are each checked when parsed, but may come from the flags and the
+    // profile separately
+    let frames = match args.frames() {
+        Some(frames) => frames,
+        None => {
+            println!("The hop can't be longer than the analysis window");
+            std::process::exit(EXIT_CONFIG);
+        }
+    };
+    let session = Session::new(&args);
+    handle_signals();
+    if session.interactive {
+        clear();
+    }
+    let range = args.range.unwrap_or(Band::FULL);
+    let opts = DiscoveryOptions {
+        bind: args.bind.unwrap_or(Ipv4Addr::UNSPECIFIED),
+        ..DiscoveryOptions::default()
+    };
+    let hbopts = HeartbeatOpts {
+        retries: session.retries(),
+        discovery: opts.clone(),
+        ..HeartbeatOpts::default()
+    };
+    match args.api_key.as_deref() {
+        // the api has its own quota so the cloud lamps aren't checked on in the background
+        Some(key) => {
+            let (group, rate) = connect_cloud(&session, &args, key, range);
+            drive(&session, &args, group, rate, Vec::new(), hbopts, frames)
+        }
+        None => {
+            let (group, devices) = connect(&session, &args, range, &opts);
+            let rate = match args.delay {
+                Some(delay) => SchedOpts::from_delay(Duration::from_millis(delay)),
+                None => SchedOpts::default(),
+            };
+            let lamps = status_lamps(&group, &devices);
+            drive(&session, &args, group, rate, lamps, hbopts, frames)
+        }
+    }
+}
+
+// settle the brightness, audio input and mode for a connected group, then run it and
+// restore the lamps
+fn drive<L: Light>(
+    session: &Session,
+    args: &Args,
+    mut group: LampGroup<L>,
+    rate: SchedOpts,
+    lamps: Vec<(Lamp, String)>,
+    opts: HeartbeatOpts,
+    frames: Frames,
+) -> ! {
+    if session.info() {
+        line();
+    }
+    // lamps of a group were each asked for their own ceiling, the group's only lowers them
+    let maxb = match args.maxb {
+        Some(maxb) => maxb,
+        None if session.interactive && group.len() == 1 => max_brightness(),
+        None => 100,
+    };
+    group.set_bright(args.bright(maxb));
+    if session.info() {
+        line();
+    }
+    let audio = match (&args.wav, args.synth, args.source.as_deref()) {
+        (Some(path), _, _) => Audio::Wav(path.clone()),
+        (None, Some(signal), _) => Audio::Synth(signal),
+        (None, None, Some("default")) => Audio::Pulse(None),
+        (None, None, Some(source)) => Audio::Pulse(Some(source.to_string())),
+        (None, None, None) if session.interactive => Audio::Pulse(audio_source()),
+        (None, None, None) => Audio::Pulse(default_source()),
+    };
+    if session.info() {
+        line();
+    }
+    let mode = match args.mode() {
+        Some(mode) => mode,
+        None if session.interactive => select_mode(),
+        None => Mode::Spectrum,
+    };
+    if session.info() {
+        line();
+    }
+    let mut sched = Scheduler::new(group, rate);
+    let code = run(session, &mut sched, lamps, opts, audio, mode, frames);
+    exit(sched.group(), code);
 }
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+// govee lan api messages, every packet is {"msg": {"cmd": ..., "data": {...}}}
+// requests go to the device on 4003 (scan to the multicast group on 4001), replies
+// come back on 4002
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message<T> {
+    pub msg: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", content = "data", rename_all = "camelCase")]
+pub enum Request {
+    Scan {
+        account_topic: String,
+    },
+    DevStatus {},
+    Turn {
+        value: u8,
+    },
+    Brightness {
+        value: u8,
+    },
+    Colorwc {
+        color: Rgb,
+        // only sent for white, 0 or missing leaves the lamp in rgb mode
+        #[serde(
+            rename = "colorTemInKelvin",
+            skip_serializing_if = "Option::is_none",
+            default
+        )]
+        temp: Option<u16>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", content = "data", rename_all = "camelCase")]
+pub enum Response {
+    Scan(Scan),
+    DevStatus(Status),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+// scan reply, versions are missing on some firmware
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Scan {
+    pub ip: String,
+    pub device: String,
+    pub sku: String,
+    #[serde(default)]
+    pub ble_version_hard: String,
+    #[serde(default)]
+    pub ble_version_soft: String,
+    #[serde(default)]
+    pub wifi_version_hard: String,
+    #[serde(default)]
+    pub wifi_version_soft: String,
+}
+
+// devStatus reply, values are checked when turned into a udp::State
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub on_off: u8,
+    pub brightness: u8,
+    pub color: Rgb,
+    #[serde(rename = "colorTemInKelvin")]
+    pub temp: u16,
+}
+
+impl Request {
+    pub fn to_vec(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&Message { msg: self })
+    }
+
+    // name of the command, for errors
+    pub fn cmd(&self) -> &'static str {
+        match self {
+            Request::Scan { .. } => "scan",
+            Request::DevStatus {} => "devStatus",
+            Request::Turn { .. } => "turn",
+            Request::Brightness { .. } => "brightness",
+            Request::Colorwc { .. } => "colorwc",
+        }
+    }
+}
+
+impl Response {
+    // numbers out of range for their field are an error rather than being truncated
+    pub fn parse(buf: &[u8]) -> serde_json::Result<Response> {
+        Ok(serde_json::from_slice::<Message<Response>>(buf)?.msg)
+    }
+
+    pub fn to_vec(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&Message { msg: self })
+    }
+}
+
+impl From<[u8; 3]> for Rgb {
+    fn from(rgb: [u8; 3]) -> Self {
+        Rgb {
+            r: rgb[0],
+            g: rgb[1],
+            b: rgb[2],
+        }
+    }
+}
+
+impl From<Rgb> for [u8; 3] {
+    fn from(rgb: Rgb) -> Self {
+        [rgb.r, rgb.g, rgb.b]
+    }
+}
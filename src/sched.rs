@@ -0,0 +1,252 @@
+use std::{
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    colproc::Update,
+    group::LampGroup,
+    udp::{Cmd, CmdErr, Lamp, Light},
+    CMDDELAY,
+};
+
+// pacing of the commands sent to each lamp, burst commands can go back to back and
+// rate more are allowed every second after that
+#[derive(Debug, Clone, Copy)]
+pub struct SchedOpts {
+    pub rate: f32,
+    pub burst: f32,
+}
+
+impl SchedOpts {
+    // one command per delay, brightness and color of a frame can go together
+    pub fn from_delay(delay: Duration) -> Self {
+        SchedOpts {
+            rate: if delay.is_zero() {
+                f32::INFINITY
+            } else {
+                1.0 / delay.as_secs_f32()
+            },
+            burst: 2.0,
+        }
+    }
+
+    // n commands a minute one at a time, for the cloud api's per device quota
+    // one short of n leaves room for the power on sent when the lamps are first driven
+    pub fn per_minute(n: u32) -> Self {
+        SchedOpts {
+            rate: n.saturating_sub(1).max(1) as f32 / 60.0,
+            burst: 1.0,
+        }
+    }
+}
+
+impl Default for SchedOpts {
+    fn default() -> Self {
+        SchedOpts::from_delay(Duration::from_millis(CMDDELAY as u64))
+    }
+}
+
+// frames are frames pushed, merged the ones that joined a pending frame, dropped the
+// values replaced before they were sent and skipped the ones equal to what the lamp
+// already has
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Metrics {
+    pub frames: u64,
+    pub merged: u64,
+    pub dropped: u64,
+    pub skipped: u64,
+    pub sent: u64,
+}
+
+// commands waiting for a lamp and the last ones it was sent, values are as they go out
+// so after brightness mapping and hue rotation
+#[derive(Debug)]
+struct Slot {
+    bright: Option<u8>,
+    color: Option<[u8; 3]>,
+    last_bright: Option<u8>,
+    last_color: Option<[u8; 3]>,
+    // color goes before brightness on the next flush, the two take turns so a level that
+    // changes every frame can't keep color from being sent
+    color_first: bool,
+    tokens: f32,
+    refilled: Instant,
+}
+
+// owns the group and sends frames as fast as each lamp allows, only the newest value of
+// each kind waits to be sent
+#[derive(Debug)]
+pub struct Scheduler<L: Light = Lamp> {
+    group: LampGroup<L>,
+    opts: SchedOpts,
+    slots: Vec<Slot>,
+    metrics: Metrics,
+}
+
+impl Slot {
+    fn new(burst: f32) -> Self {
+        Slot {
+            bright: None,
+            color: None,
+            last_bright: None,
+            last_color: None,
+            color_first: false,
+            tokens: burst,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn pending(&self) -> bool {
+        self.bright.is_some() || self.color.is_some()
+    }
+
+    fn refill(&mut self, opts: &SchedOpts, now: Instant) {
+        let secs = now.duration_since(self.refilled).as_secs_f32();
+        self.tokens = if opts.rate.is_infinite() {
+            opts.burst
+        } else {
+            (self.tokens + secs * opts.rate).min(opts.burst)
+        };
+        self.refilled = now;
+    }
+
+    // take a token if there is one
+    fn take(&mut self) -> bool {
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// queue val replacing anything pending, nothing is queued if the lamp already has it
+fn queue<T: PartialEq>(pending: &mut Option<T>, last: &Option<T>, val: T, metrics: &mut Metrics) {
+    if pending.take().is_some() {
+        metrics.dropped += 1;
+    }
+    if last.as_ref() == Some(&val) {
+        metrics.skipped += 1;
+    } else {
+        *pending = Some(val);
+    }
+}
+
+impl<L: Light> Scheduler<L> {
+    pub fn new(group: LampGroup<L>, opts: SchedOpts) -> Self {
+        let slots = (0..group.len()).map(|_| Slot::new(opts.burst)).collect();
+        Scheduler {
+            group,
+            opts,
+            slots,
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn group(&self) -> &LampGroup<L> {
+        &self.group
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    // add a frame of per lamp updates, sent on the next flush the rate allows
+    pub fn push(&mut self, updates: Vec<Update>) {
+        self.metrics.frames += 1;
+        let members = self.group.members();
+        for ((slot, member), update) in self.slots.iter_mut().zip(members).zip(updates) {
+            if slot.pending() {
+                self.metrics.merged += 1;
+            }
+            if let Update::Both(level, _) | Update::Brightness(level) = update {
+                let val = member.brightness(level);
+                queue(&mut slot.bright, &slot.last_bright, val, &mut self.metrics);
+            }
+            if let Update::Both(_, rgb) | Update::Color(rgb) = update {
+                let val = member.color(rgb);
+                queue(&mut slot.color, &slot.last_color, val, &mut self.metrics);
+            }
+        }
+    }
+
+    // send whatever the rate allows, brightness and color take turns going first, the
+    // last error is returned
+    // a command that fails to send is forgotten so the next value isn't skipped
+    pub fn flush(&mut self) -> Result<(), CmdErr> {
+        let now = Instant::now();
+        let mut res = Ok(());
+        for (slot, member) in self.slots.iter_mut().zip(self.group.members()) {
+            slot.refill(&self.opts, now);
+            let first = slot.color_first;
+            for color in [first, !first] {
+                if color {
+                    if let Some(val) = slot.color.filter(|_| slot.take()) {
+                        slot.color = None;
+                        slot.last_color = None;
+                        slot.color_first = false;
+                        match member.lamp.send_cmd(Cmd::Color(val)) {
+                            Ok(_) => {
+                                slot.last_color = Some(val);
+                                self.metrics.sent += 1;
+                            }
+                            Err(err) => res = Err(err),
+                        }
+                    }
+                } else if let Some(val) = slot.bright.filter(|_| slot.take()) {
+                    slot.bright = None;
+                    slot.last_bright = None;
+                    slot.color_first = true;
+                    match member.lamp.send_cmd(Cmd::Brightness(val)) {
+                        Ok(_) => {
+                            slot.last_bright = Some(val);
+                            self.metrics.sent += 1;
+                        }
+                        Err(err) => res = Err(err),
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    // how long until a pending command can be sent, None if nothing is pending
+    pub fn wait(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.slots
+            .iter()
+            .filter(|slot| slot.pending())
+            .map(|slot| {
+                if self.opts.rate.is_infinite() {
+                    return Duration::ZERO;
+                }
+                let secs = now.duration_since(slot.refilled).as_secs_f32();
+                let tokens = slot.tokens + secs * self.opts.rate;
+                if tokens >= 1.0 {
+                    Duration::ZERO
+                } else {
+                    Duration::from_secs_f32((1.0 - tokens) / self.opts.rate)
+                }
+            })
+            .min()
+    }
+
+    // point a lamp at its new address, everything is sent to it again
+    pub fn set_ip(&mut self, index: usize, ip: Ipv4Addr) {
+        self.group.set_ip(index, ip);
+        if let Some(slot) = self.slots.get_mut(index) {
+            slot.last_bright = None;
+            slot.last_color = None;
+        }
+    }
+
+    // forget what the lamps were last sent, after a reconnect they may have changed
+    pub fn forget(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.last_bright = None;
+            slot.last_color = None;
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use dft::{Operation, Plan};
+use std::f32::consts::TAU;
+
+// window applied to each frame before the transform
+#[derive(Debug, Clone, Copy)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    BlackmanHarris,
+}
+
+impl Window {
+    // window coefficients for a frame of len samples
+    fn coefficients(&self, len: usize) -> Vec<f32> {
+        let n = len as f32;
+        (0..len)
+            .map(|i| {
+                let x = TAU * i as f32 / n;
+                match self {
+                    Window::Rectangular => 1.0,
+                    Window::Hann => 0.5 - 0.5 * x.cos(),
+                    Window::Hamming => 0.54 - 0.46 * x.cos(),
+                    Window::BlackmanHarris => {
+                        0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos()
+                            - 0.01168 * (3.0 * x).cos()
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+// reusable spectrum analyzer, keeps the plan, window and buffers between frames
+// output holds len / 2 bins, bin i is i * rate / len hz
+pub struct Spectrum {
+    plan: Plan<f32>,
+    window: Vec<f32>,
+    gain: f32,
+    scratch: Vec<f32>,
+    output: Vec<f32>,
+}
+
+impl Spectrum {
+    // len must be a power of two
+    pub fn new(len: usize, window: Window) -> Self {
+        assert!(len.is_power_of_two());
+        let window = window.coefficients(len);
+        // coherent gain, so windowed magnitudes stay comparable to unwindowed ones
+        let gain = window.iter().sum::<f32>() / len as f32;
+        Spectrum {
+            plan: Plan::new(Operation::Forward, len),
+            window,
+            gain,
+            scratch: vec![0.0; len],
+            output: vec![0.0; len / 2],
+        }
+    }
+
+    // frame length in samples
+    pub fn len(&self) -> usize {
+        self.window.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    // linear magnitude of each bin, frames shorter than len are zero padded
+    pub fn magnitude(&mut self, data: &[f32]) -> &[f32] {
+        self.transform(data);
+        let gain = self.gain;
+        self.fill(|re, im| (re * re + im * im).sqrt() / gain);
+        &self.output
+    }
+
+    // magnitude of each bin in dB relative to a full scale sine
+    pub fn db(&mut self, data: &[f32]) -> &[f32] {
+        self.transform(data);
+        let scale = 2.0 / (self.gain * self.len() as f32);
+        self.fill(|re, im| 20.0 * ((re * re + im * im).sqrt() * scale).max(1e-10).log10());
+        &self.output
+    }
+
+    fn transform(&mut self, data: &[f32]) {
+        for (i, (out, w)) in self.scratch.iter_mut().zip(self.window.iter()).enumerate() {
+            *out = data.get(i).copied().unwrap_or(0.0) * w;
+        }
+        dft::transform(&mut self.scratch, &self.plan);
+    }
+
+    // packed real transform, scratch[0] is dc and pairs from 2 on are re, im
+    fn fill<F: Fn(f32, f32) -> f32>(&mut self, f: F) {
+        self.output[0] = f(self.scratch[0], 0.0);
+        for i in 1..self.output.len() {
+            self.output[i] = f(self.scratch[2 * i], self.scratch[2 * i + 1]);
+        }
+    }
+}
+
+// how the analyzer spaces its bands
+#[derive(Debug, Clone, Copy)]
+pub enum Scale {
+    // count log spaced bands
+    Log(usize),
+    // bands per octave
+    Octave(usize),
+    // count bands spaced evenly on the mel scale
+    Mel(usize),
+}
+
+// where the color comes from
+#[derive(Debug, Clone, Copy)]
+pub enum Hue {
+    // energy weighted centre of the bands
+    Centroid,
+    // loudest band
+    Dominant,
+}
+
+// groups spectrum bins into bands between min and max hz
+pub struct BandAnalyzer {
+    edges: Vec<f32>,
+    bins: Vec<Option<usize>>,
+    energy: Vec<f32>,
+    scale: f32,
+}
+
+impl BandAnalyzer {
+    // bins is the spectrum length, half the window, sampled at rate
+    pub fn new(scale: Scale, min: f32, max: f32, bins: usize, rate: u32) -> Self {
+        let mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+        let hz = |mel: f32| 700.0 * (10f32.powf(mel / 2595.0) - 1.0);
+        let log_edges = |count: usize| -> Vec<f32> {
+            (0..=count)
+                .map(|i| min * (max / min).powf(i as f32 / count as f32))
+                .collect()
+        };
+        let edges: Vec<f32> = match scale {
+            Scale::Log(count) => log_edges(count.max(1)),
+            Scale::Octave(per) => log_edges(((max / min).log2() * per as f32).ceil() as usize),
+            Scale::Mel(count) => {
+                let count = count.max(1);
+                let (lo, hi) = (mel(min), mel(max));
+                (0..=count)
+                    .map(|i| hz(lo + (hi - lo) * i as f32 / count as f32))
+                    .collect()
+            }
+        };
+
+        // band of every bin, looked up once here instead of every frame
+        let width = rate as f32 / (bins * 2) as f32;
+        let bands = edges.len() - 1;
+        let lookup = (0..bins)
+            .map(|i| {
+                let f = i as f32 * width;
+                if i == 0 || f < min || f > max {
+                    return None;
+                }
+                Some(
+                    edges[1..]
+                        .iter()
+                        .position(|edge| f <= *edge)
+                        .unwrap_or(bands - 1),
+                )
+            })
+            .collect();
+
+        BandAnalyzer {
+            energy: vec![0.0; bands],
+            edges,
+            bins: lookup,
+            // a full scale sine peaks at half the window length
+            scale: 20.0 * (1.0 / bins as f32).log10(),
+        }
+    }
+
+    // band edges in hz, one more than there are bands
+    pub fn edges(&self) -> &[f32] {
+        &self.edges
+    }
+
+    // power in each band for one magnitude spectrum
+    pub fn analyze(&mut self, mag: &[f32]) -> &[f32] {
+        self.energy.iter_mut().for_each(|e| *e = 0.0);
+        for (m, band) in mag.iter().zip(self.bins.iter()) {
+            if let Some(band) = band {
+                self.energy[*band] += m * m;
+            }
+        }
+        &self.energy
+    }
+
+    // total level of the last analyzed frame in dB relative to a full scale sine
+    pub fn level(&self) -> f32 {
+        10.0 * self.energy.iter().sum::<f32>().log10() + self.scale
+    }
+
+    // position of the last analyzed frame across the bands, 0 at min and 1 at max
+    pub fn position(&self, hue: Hue) -> f32 {
+        let bands = self.energy.len() as f32;
+        match hue {
+            Hue::Centroid => {
+                let total: f32 = self.energy.iter().sum();
+                if total <= 0.0 {
+                    return 0.0;
+                }
+                self.energy
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| (i as f32 + 0.5) * e)
+                    .sum::<f32>()
+                    / total
+                    / bands
+            }
+            Hue::Dominant => {
+                let (index, _) = self
+                    .energy
+                    .iter()
+                    .enumerate()
+                    .fold(
+                        (0, 0.0),
+                        |best, (i, e)| if *e > best.1 { (i, *e) } else { best },
+                    );
+                (index as f32 + 0.5) / bands
+            }
+        }
+    }
+}
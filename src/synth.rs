@@ -0,0 +1,110 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::f32::consts::TAU;
+
+use crate::{audproc::AudioSource, LampErr, RATE};
+
+// click length in samples, 10ms
+const CLICK: u64 = RATE as u64 / 100;
+
+// synthetic test signals
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    // fixed frequency in hz
+    Sine(f32),
+    // exponential sweep between two frequencies, repeating every secs
+    Sweep { from: f32, to: f32, secs: f32 },
+    // white noise
+    Noise,
+    // decaying 1khz click at the given bpm
+    Clicks(f32),
+}
+
+impl Signal {
+    // sine=HZ, sweep, sweep=FROM-TO, noise, clicks or clicks=BPM
+    pub fn parse(val: &str) -> Option<Signal> {
+        let (name, arg) = match val.split_once('=') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (val, None),
+        };
+        let num = |val: &str| val.trim().parse::<f32>().ok().filter(|n| *n > 0.0);
+        match (name, arg) {
+            ("sine", Some(hz)) => Some(Signal::Sine(num(hz)?)),
+            ("sweep", None) => Some(Signal::Sweep {
+                from: 20.0,
+                to: 20000.0,
+                secs: 10.0,
+            }),
+            ("sweep", Some(range)) => {
+                let (from, to) = range.split_once('-')?;
+                Some(Signal::Sweep {
+                    from: num(from)?,
+                    to: num(to)?,
+                    secs: 10.0,
+                })
+            }
+            ("noise", None) => Some(Signal::Noise),
+            ("clicks", None) => Some(Signal::Clicks(120.0)),
+            ("clicks", Some(bpm)) => Some(Signal::Clicks(num(bpm)?)),
+            _ => None,
+        }
+    }
+}
+
+// endless generator for a Signal
+pub struct Synth {
+    signal: Signal,
+    amplitude: f32,
+    sample: u64,
+    phase: f32,
+    rng: StdRng,
+}
+
+impl Synth {
+    pub fn new(signal: Signal, amplitude: f32) -> Self {
+        Synth {
+            signal,
+            amplitude,
+            sample: 0,
+            phase: 0.0,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+
+    fn next(&mut self) -> f32 {
+        let t = self.sample as f32 / RATE as f32;
+        let value = match self.signal {
+            Signal::Sine(hz) => self.advance(hz),
+            Signal::Sweep { from, to, secs } => {
+                let t = t % secs;
+                let hz = from * (to / from).powf(t / secs);
+                self.advance(hz)
+            }
+            Signal::Noise => self.rng.gen_range(-1.0..=1.0),
+            Signal::Clicks(bpm) => {
+                let period = (60.0 / bpm * RATE as f32) as u64;
+                let offset = self.sample % period.max(1);
+                if offset < CLICK {
+                    let decay = (-(offset as f32) / (CLICK as f32 / 4.0)).exp();
+                    (TAU * 1000.0 * offset as f32 / RATE as f32).sin() * decay
+                } else {
+                    0.0
+                }
+            }
+        };
+        self.sample += 1;
+        value * self.amplitude
+    }
+
+    // step the oscillator phase and return its value
+    fn advance(&mut self, hz: f32) -> f32 {
+        let value = self.phase.sin();
+        self.phase = (self.phase + TAU * hz / RATE as f32) % TAU;
+        value
+    }
+}
+
+impl AudioSource for Synth {
+    fn frame(&mut self, len: usize) -> Result<Option<Vec<f32>>, LampErr> {
+        Ok(Some((0..len).map(|_| self.next()).collect()))
+    }
+}
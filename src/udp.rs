@@ -1,21 +1,37 @@
-use serde_json::{json, Value};
+use serde::{Deserialize, Serialize};
 use std::{
-    net::{AddrParseError, Ipv4Addr, SocketAddrV4, UdpSocket},
+    collections::HashSet,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4, UdpSocket},
     num::ParseIntError,
     str::FromStr,
+    thread,
+    time::{Duration, Instant},
 };
 // use arr_macro::arr;
 
+use crate::{
+    protocol::{Request, Response, Scan, Status},
+    CMDDELAY, SCANTIMEOUT,
+};
+
+// how long to wait for a devStatus reply
+const STATUSTIMEOUT: Duration = Duration::from_millis(1000);
+// restore attempts before giving up on a lamp
+const RESTORE_TRIES: usize = 3;
+
 // cmd types
 #[derive(Debug)]
 pub enum Cmd {
     OnOff(Turn),
     Brightness(u8),
     Color([u8; 3]),
+    // white at a color temperature in kelvin
+    Temp(u16),
 }
 
 // on, or maybe off
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Turn {
     On,
     Off,
@@ -25,15 +41,31 @@ pub enum Turn {
 #[derive(Debug)]
 pub enum CmdErr {
     ParseIntErr,
-    SerdeErr,
-    InvalidBrightnessErr,
-    MiscCmdErr,
-    RecvErr,
+    // malformed or truncated json, holds serde's message with the position
+    SerdeErr(String),
+    InvalidBrightnessErr(u8),
+    // a reply field parsed but isn't a value the lamp can be in
+    InvalidFieldErr { field: &'static str, value: u64 },
+    MiscCmdErr(ErrorKind),
+    // no reply, or the socket failed while waiting for one
+    RecvErr(ErrorKind),
+    // state read back after a restore didn't match
+    RestoreErr,
+    // the request never got an http response
+    HttpErr(String),
+    // the cloud api answered with an error, holds its code and message
+    ApiErr { code: u16, msg: String },
+    // out of api quota, holds how long until requests are allowed again
+    RateLimitErr(Duration),
+    // the device doesn't take this command, holds its api name
+    UnsupportedErr(&'static str),
+    // the cloud can't reach the device
+    OfflineErr,
 }
 
 impl From<std::io::Error> for CmdErr {
-    fn from(_: std::io::Error) -> Self {
-        CmdErr::MiscCmdErr
+    fn from(err: std::io::Error) -> Self {
+        CmdErr::MiscCmdErr(err.kind())
     }
 }
 
@@ -44,54 +76,147 @@ impl From<ParseIntError> for CmdErr {
 }
 
 impl From<serde_json::Error> for CmdErr {
-    fn from(_: serde_json::Error) -> Self {
-        CmdErr::SerdeErr
+    fn from(err: serde_json::Error) -> Self {
+        CmdErr::SerdeErr(err.to_string())
     }
 }
 
 // init error types
 #[derive(Debug)]
 pub enum InitErr {
-    AddrParseErr,
-    MiscInitErr,
-    SerdeErr,
-    DevStatusErr,
+    // holds the address that didn't parse
+    AddrParseErr(String),
+    MiscInitErr(ErrorKind),
+    SerdeErr(String),
+    // the lamp was found but its status couldn't be read
+    DevStatusErr(CmdErr),
+    NotFoundErr,
+    // the reply port is usually taken by another instance
+    BindErr { addr: SocketAddrV4, kind: ErrorKind },
+    // the scan couldn't be sent, usually no route to the multicast group
+    ScanErr(ErrorKind),
 }
 
-impl From<AddrParseError> for InitErr {
-    fn from(_: AddrParseError) -> Self {
-        InitErr::AddrParseErr
+impl Cmd {
+    // the lan api request for a command, brightness is checked here
+    pub fn request(self) -> Result<Request, CmdErr> {
+        Ok(match self {
+            Cmd::OnOff(val) => Request::Turn {
+                value: match val {
+                    Turn::On => 1,
+                    Turn::Off => 0,
+                },
+            },
+            Cmd::Brightness(val) => {
+                if val > 100 {
+                    return Err(CmdErr::InvalidBrightnessErr(val));
+                }
+                Request::Brightness { value: val }
+            }
+            Cmd::Color(val) => Request::Colorwc {
+                color: val.into(),
+                temp: None,
+            },
+            Cmd::Temp(val) => Request::Colorwc {
+                color: [0, 0, 0].into(),
+                temp: Some(val),
+            },
+        })
     }
 }
 
 impl From<std::io::Error> for InitErr {
-    fn from(_: std::io::Error) -> Self {
-        InitErr::MiscInitErr
+    fn from(err: std::io::Error) -> Self {
+        InitErr::MiscInitErr(err.kind())
     }
 }
 
 impl From<serde_json::Error> for InitErr {
-    fn from(_: serde_json::Error) -> Self {
-        InitErr::SerdeErr
+    fn from(err: serde_json::Error) -> Self {
+        InitErr::SerdeErr(err.to_string())
     }
 }
 
 impl From<CmdErr> for InitErr {
-    fn from(_: CmdErr) -> Self {
-        InitErr::DevStatusErr
+    fn from(err: CmdErr) -> Self {
+        InitErr::DevStatusErr(err)
     }
 }
 
-// socket, address, init state, max brightness
+// socket, address, init state, levels are mapped to brightness by group::Member
 #[derive(Debug)]
 pub struct Lamp {
     socket: UdpSocket,
     pub addr: SocketAddrV4,
     pub init: State,
-    maxb: u8,
 }
 
-#[derive(Debug)]
+// scan response from a device on the lan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub device: String,
+    pub sku: String,
+    pub mac: String,
+    pub ip: Ipv4Addr,
+    pub firmware: Firmware,
+}
+
+// hardware and software versions reported by scan
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Firmware {
+    pub ble_hard: String,
+    pub ble_soft: String,
+    pub wifi_hard: String,
+    pub wifi_soft: String,
+}
+
+impl DeviceInfo {
+    // a lamp known only by its address, for connecting without a scan
+    pub fn from_ip(ip: Ipv4Addr) -> Self {
+        DeviceInfo {
+            device: String::new(),
+            sku: "Unknown".to_string(),
+            mac: String::new(),
+            ip,
+            firmware: Firmware::default(),
+        }
+    }
+}
+
+impl TryFrom<Scan> for DeviceInfo {
+    type Error = InitErr;
+
+    fn try_from(scan: Scan) -> Result<Self, InitErr> {
+        let ip = match Ipv4Addr::from_str(&scan.ip) {
+            Ok(ip) => ip,
+            Err(_) => return Err(InitErr::AddrParseErr(scan.ip)),
+        };
+        if scan.device.is_empty() {
+            return Err(InitErr::SerdeErr(
+                "scan reply without a device id".to_string(),
+            ));
+        }
+
+        // device id is the mac address with two extra leading bytes
+        let octets: Vec<&str> = scan.device.split(':').collect();
+        let mac = octets[octets.len().saturating_sub(6)..].join(":");
+
+        Ok(DeviceInfo {
+            sku: scan.sku,
+            mac,
+            ip,
+            firmware: Firmware {
+                ble_hard: scan.ble_version_hard,
+                ble_soft: scan.ble_version_soft,
+                wifi_hard: scan.wifi_version_hard,
+                wifi_soft: scan.wifi_version_soft,
+            },
+            device: scan.device,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct State {
     pub pwr: Turn,
     pub bright: u8,
@@ -99,112 +224,138 @@ pub struct State {
     pub temp: u16,
 }
 
-pub enum CmdValue {
-    Single(u8),
-    RGB([u8; 3]),
+impl TryFrom<Status> for State {
+    type Error = CmdErr;
+
+    fn try_from(status: Status) -> Result<Self, CmdErr> {
+        let pwr = match status.on_off {
+            1 => Turn::On,
+            0 => Turn::Off,
+            value => {
+                return Err(CmdErr::InvalidFieldErr {
+                    field: "onOff",
+                    value: value as u64,
+                })
+            }
+        };
+        if status.brightness > 100 {
+            return Err(CmdErr::InvalidFieldErr {
+                field: "brightness",
+                value: status.brightness as u64,
+            });
+        }
+        Ok(State {
+            pwr,
+            bright: status.brightness,
+            color: status.color.into(),
+            temp: status.temp,
+        })
+    }
 }
 
-impl Lamp {
-    fn new(socket: UdpSocket, addr: SocketAddrV4, init: State) -> Self {
-        Lamp {
-            socket,
-            addr,
-            init,
-            maxb: 0,
-        }
+impl State {
+    // a colorTemInKelvin of 0 means the lamp is in rgb mode
+    pub fn is_temp(&self) -> bool {
+        self.temp > 0
     }
 
-    // send any command to lamp over udp
-    pub fn send_cmd(&self, cmd: Cmd) -> Result<(), CmdErr> {
-        let (command, value) = match cmd {
-            Cmd::OnOff(val) => {
-                let command = "turn";
-                let value = match val {
-                    Turn::On => 1,
-                    Turn::Off => 0,
-                };
-                (command, CmdValue::Single(value))
-            }
-            Cmd::Brightness(val) => {
-                if val > 100 {
-                    return Err(CmdErr::InvalidBrightnessErr);
-                }
-                let command = "brightness";
-                (command, CmdValue::Single(val))
-            }
-            Cmd::Color(val) => {
-                let command = "colorwc";
-                (command, CmdValue::RGB(val))
-            }
+    // commands that put a lamp in this state, power on comes first so the rest lands on a
+    // lit lamp, power off last since brightness and color commands can turn the lamp
+    // back on
+    pub fn cmds(&self) -> [Cmd; 3] {
+        let color = if self.is_temp() {
+            Cmd::Temp(self.temp)
+        } else {
+            Cmd::Color(self.color)
         };
+        match self.pwr {
+            Turn::On => [Cmd::OnOff(Turn::On), Cmd::Brightness(self.bright), color],
+            Turn::Off => [Cmd::Brightness(self.bright), color, Cmd::OnOff(Turn::Off)],
+        }
+    }
 
-        let msg = match value {
-            CmdValue::Single(val) => serde_json::to_vec(&json!({
-                "msg": {
-                    "cmd": command,
-                    "data": {
-                        "value": val
-                    }
-                }
-            }))?,
-            CmdValue::RGB(val) => serde_json::to_vec(&json!({
-                "msg": {
-                    "cmd": command,
-                    "data": {
-                        "color": {
-                            "r": val[0],
-                            "g": val[1],
-                            "b": val[2]
-                        }
-                    }
-                }
-            }))?,
+    // whether the lamp reads back as this state, only the active color mode is compared
+    pub fn restored(&self, read: &State) -> bool {
+        let color = if self.is_temp() {
+            self.temp == read.temp
+        } else {
+            !read.is_temp() && self.color == read.color
         };
+        self.pwr == read.pwr && self.bright == read.bright && color
+    }
+}
 
-        self.socket.send_to(&msg, self.addr)?;
+impl Lamp {
+    // skip the scan and ask the lamp at ip for its status directly, for networks that
+    // block multicast
+    pub fn connect(ip: Ipv4Addr) -> Result<Lamp, InitErr> {
+        Lamp::connect_with(ip, &DiscoveryOptions::default())
+    }
+
+    pub fn connect_with(ip: Ipv4Addr, opts: &DiscoveryOptions) -> Result<Lamp, InitErr> {
+        open(opts.bind()?, ip, opts)
+    }
+
+    fn new(socket: UdpSocket, addr: SocketAddrV4, init: State) -> Self {
+        Lamp { socket, addr, init }
+    }
+
+    // send any command to lamp over udp
+    pub fn send_cmd(&self, cmd: Cmd) -> Result<(), CmdErr> {
+        self.socket.send_to(&cmd.request()?.to_vec()?, self.addr)?;
 
         Ok(())
     }
 
+    // put the lamp back to its initial state one command at a time, then read the state
+    // back and try again if the lamp didn't take it
     pub fn restore(&self) -> Result<(), CmdErr> {
-        let pwr = match self.init.pwr {
-            Turn::Off => 0,
-            Turn::On => 1,
-        };
-        let bright = self.init.bright;
-        let color = self.init.color;
-        let temp = self.init.temp;
-
-        let msg = serde_json::to_vec(&json!({
-            "msg": {
-                "cmd": "turn",
-                "data": {
-                    "value": pwr
-                }
-            },
-            "msg": {
-                "cmd": "brightness",
-                "data": {
-                    "value": bright
-                }
-            },
-            "msg": {
-                "cmd": "colorwc",
-                "data": {
-                    "color": {
-                        "r": color[0],
-                        "g": color[1],
-                        "b": color[2]
-                    },
-                    "colorTemInKelvin": temp
-                }
-            }
-        }))?;
+        let mut res = Err(CmdErr::RestoreErr);
+        for _ in 0..RESTORE_TRIES {
+            self.send_state(&self.init)?;
+            thread::sleep(Duration::from_millis(CMDDELAY as u64));
+            res = match dev_status(&self.socket, &self.addr) {
+                Ok(read) if self.init.restored(&read) => return Ok(()),
+                Ok(_) => Err(CmdErr::RestoreErr),
+                Err(err) => Err(err),
+            };
+        }
+        res
+    }
 
-        self.socket.send_to(&msg, self.addr)?;
+    // send the state's commands paced by CMDDELAY
+    fn send_state(&self, state: &State) -> Result<(), CmdErr> {
+        for (i, cmd) in state.cmds().into_iter().enumerate() {
+            if i > 0 {
+                thread::sleep(Duration::from_millis(CMDDELAY as u64));
+            }
+            self.send_cmd(cmd)?;
+        }
         Ok(())
     }
 
+    // second handle to the lamp, commands and status checks go through the same socket
+    pub fn try_clone(&self) -> Result<Lamp, InitErr> {
+        Ok(Lamp {
+            socket: self.socket.try_clone()?,
+            addr: self.addr,
+            init: self.init.clone(),
+        })
+    }
+
+    // scan from the lamp's socket, the reply port is already bound by it so discover
+    // can't be used while connected
+    pub fn scan(&self, opts: &DiscoveryOptions) -> Result<Vec<DeviceInfo>, InitErr> {
+        let res = scan(&self.socket, opts);
+        self.socket.set_read_timeout(Some(opts.read_timeout))?;
+        res
+    }
+
+    // current state of the lamp
+    pub fn status(&self) -> Result<State, CmdErr> {
+        dev_status(&self.socket, &self.addr)
+    }
+
     // check if still connected
     pub fn check(&self) -> Result<(), CmdErr> {
         match dev_status(&self.socket, &self.addr) {
@@ -212,133 +363,230 @@ impl Lamp {
             Err(err) => Err(err),
         }
     }
+}
 
-    // set max brightness
-    pub fn set_maxb(&mut self, maxb: u8) {
-        self.maxb = maxb
-    }
+// what every backend does with a lamp, Lamp over the lan and cloud::CloudLamp over
+// the govee http api
+pub trait Light {
+    fn send_cmd(&self, cmd: Cmd) -> Result<(), CmdErr>;
+
+    fn status(&self) -> Result<State, CmdErr>;
+
+    // state when connected, what restore goes back to
+    fn init(&self) -> &State;
+
+    fn restore(&self) -> Result<(), CmdErr>;
 
-    // return max brightness
-    pub fn maxb(&self) -> u8 {
-        self.maxb
+    fn check(&self) -> Result<(), CmdErr> {
+        self.status().map(|_| ())
     }
-}
 
-// creates udp socket, joins the multicast group, queries device
-// returns Lamp struct with socket and ip of first device to respond
-pub fn init() -> Result<Lamp, InitErr> {
-    let socket = UdpSocket::bind("0.0.0.0:4002").expect("failed to bind");
-    socket.set_multicast_ttl_v4(1)?;
-
-    let multicast_addr = Ipv4Addr::from([239, 255, 255, 250]);
-    let port = 4001;
-    let multicast_socket = SocketAddrV4::new(multicast_addr, port);
-
-    let msg = serde_json::to_vec(&json!({
-        "msg": {
-            "cmd": "scan",
-            "data": {
-                "account_topic": "reserve"
-            }
-        }
-    }))?;
+    // point it at the address it moved to, only lan lamps have one
+    fn set_ip(&mut self, _ip: Ipv4Addr) {}
+}
 
-    socket
-        .send_to(&msg, multicast_socket)
-        .expect("failed to send to multicast socket");
+impl Light for Lamp {
+    fn send_cmd(&self, cmd: Cmd) -> Result<(), CmdErr> {
+        Lamp::send_cmd(self, cmd)
+    }
 
-    let mut buf = [0u8; 256];
-    socket.recv_from(&mut buf)?;
+    fn status(&self) -> Result<State, CmdErr> {
+        Lamp::status(self)
+    }
 
-    let json = trimmer(&buf);
+    fn init(&self) -> &State {
+        &self.init
+    }
 
-    let ip = match json["msg"]["data"]["ip"].as_str() {
-        Some(ip) => ip,
-        None => return Err(InitErr::AddrParseErr),
-    };
-    let addr = SocketAddrV4::new(Ipv4Addr::from_str(ip)?, 4003);
-    let init = dev_status(&socket, &addr)?;
+    fn restore(&self) -> Result<(), CmdErr> {
+        Lamp::restore(self)
+    }
 
-    Ok(Lamp::new(socket, addr, init))
+    fn set_ip(&mut self, ip: Ipv4Addr) {
+        self.addr.set_ip(ip)
+    }
 }
 
-// get lamp status
-fn dev_status(socket: &UdpSocket, addr: &SocketAddrV4) -> Result<State, CmdErr> {
-    let msg = serde_json::to_vec(&json!({
-        "msg": {
-            "cmd": "devStatus",
-            "data": {
+// where and how to look for lamps, the defaults are the govee lan api's
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions {
+    // local address to bind, picks the interface scans go out on, UNSPECIFIED for the
+    // default route
+    pub bind: Ipv4Addr,
+    pub multicast: Ipv4Addr,
+    // lamps listen for scans on scan_port and commands on cmd_port, every reply comes
+    // back on reply_port
+    pub scan_port: u16,
+    pub reply_port: u16,
+    pub cmd_port: u16,
+    pub ttl: u32,
+    // how long to collect scan replies after each scan
+    pub timeout: Duration,
+    // how long to wait for a devStatus reply
+    pub read_timeout: Duration,
+    // extra scans sent when nothing answered
+    pub retries: usize,
+}
 
-            }
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        DiscoveryOptions {
+            bind: Ipv4Addr::UNSPECIFIED,
+            multicast: Ipv4Addr::new(239, 255, 255, 250),
+            scan_port: 4001,
+            reply_port: 4002,
+            cmd_port: 4003,
+            ttl: 1,
+            timeout: Duration::from_millis(SCANTIMEOUT as u64),
+            read_timeout: STATUSTIMEOUT,
+            retries: 0,
         }
-    }))?;
-
-    socket.send_to(&msg, addr)?;
-
-    let mut recv_buf = [0u8; 256];
-    socket.recv_from(&mut recv_buf).or(Err(CmdErr::RecvErr))?;
-
-    let recv = trimmer(&recv_buf);
-
-    // json! macro can't be used in match statements??
-    let pwr = if recv["msg"]["data"]["onOff"] == json!(1) {
-        Turn::On
-    } else if recv["msg"]["data"]["offOff"] == json!(0) {
-        Turn::Off
-    } else {
-        return Err(CmdErr::RecvErr);
-    };
-
-    let bright = match &recv["msg"]["data"]["brightness"] {
-        Value::Number(num) => num.as_u64().unwrap_or(0) as u8,
-        _ => return Err(CmdErr::RecvErr),
-    };
-
-    let r = match &recv["msg"]["data"]["color"]["r"] {
-        Value::Number(num) => num.as_u64().unwrap_or(0) as u8,
-        _ => return Err(CmdErr::RecvErr),
-    };
-    let g = match &recv["msg"]["data"]["color"]["g"] {
-        Value::Number(num) => num.as_u64().unwrap_or(0) as u8,
-        _ => return Err(CmdErr::RecvErr),
-    };
-    let b = match &recv["msg"]["data"]["color"]["b"] {
-        Value::Number(num) => num.as_u64().unwrap_or(0) as u8,
-        _ => return Err(CmdErr::RecvErr),
-    };
-
-    let color = [r, g, b];
-
-    let temp = match &recv["msg"]["data"]["colorTemInKelvin"] {
-        Value::Number(num) => num.as_u64().unwrap_or(0) as u16,
-        _ => return Err(CmdErr::RecvErr),
-    };
-
-    Ok(State {
-        pwr,
-        bright,
-        color,
-        temp,
+    }
+}
+
+impl DiscoveryOptions {
+    // socket replies come back on
+    pub(crate) fn bind(&self) -> Result<UdpSocket, InitErr> {
+        let addr = SocketAddrV4::new(self.bind, self.reply_port);
+        UdpSocket::bind(addr).map_err(|err| InitErr::BindErr {
+            addr,
+            kind: err.kind(),
+        })
+    }
+}
+
+// sends a scan to the multicast group and collects every device that responds before
+// the timeout
+pub fn discover(timeout: Duration) -> Result<Vec<DeviceInfo>, InitErr> {
+    discover_with(&DiscoveryOptions {
+        timeout,
+        ..DiscoveryOptions::default()
     })
 }
 
-// trims whitespace from response buffer, could be more efficient but don't feel like fixing it
-fn trimmer(buf: &[u8]) -> Value {
-    let mut end = buf.len() - 1;
-    let mut trav: u8 = buf[end];
-    while trav == 0 {
-        end -= 1;
-        trav = buf[end];
+// same as discover, scanning again up to opts.retries times while nothing answers
+pub fn discover_with(opts: &DiscoveryOptions) -> Result<Vec<DeviceInfo>, InitErr> {
+    scan(&opts.bind()?, opts)
+}
+
+fn scan(socket: &UdpSocket, opts: &DiscoveryOptions) -> Result<Vec<DeviceInfo>, InitErr> {
+    socket.set_multicast_ttl_v4(opts.ttl)?;
+    let group = SocketAddrV4::new(opts.multicast, opts.scan_port);
+
+    let msg = Request::Scan {
+        account_topic: "reserve".to_string(),
+    }
+    .to_vec()?;
+
+    let mut devices: Vec<DeviceInfo> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut buf = [0u8; 1024];
+    for _ in 0..=opts.retries {
+        socket
+            .send_to(&msg, group)
+            .map_err(|err| InitErr::ScanErr(err.kind()))?;
+
+        let deadline = Instant::now() + opts.timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            socket.set_read_timeout(Some(deadline - now))?;
+            let len = match socket.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    break
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            // ignore anything that isn't a valid scan response
+            let scan = match Response::parse(&buf[..len]) {
+                Ok(Response::Scan(scan)) => scan,
+                _ => continue,
+            };
+            if let Ok(info) = DeviceInfo::try_from(scan) {
+                if seen.insert(info.device.clone()) {
+                    devices.push(info);
+                }
+            }
+        }
+
+        if !devices.is_empty() {
+            break;
+        }
     }
 
-    let length = end + 1;
-    let mut trim: Vec<u8> = Vec::with_capacity(length);
+    Ok(devices)
+}
 
-    for i in buf.iter().take(length) {
-        trim.push(*i);
+// creates udp socket and queries the chosen device
+// returns Lamp struct with socket and ip of the device
+pub fn connect(info: &DeviceInfo) -> Result<Lamp, InitErr> {
+    connect_with(info, &DiscoveryOptions::default())
+}
+
+pub fn connect_with(info: &DeviceInfo, opts: &DiscoveryOptions) -> Result<Lamp, InitErr> {
+    open(opts.bind()?, info.ip, opts)
+}
+
+// same as connect for several devices, every lamp shares one socket since replies
+// all come back on the reply port
+pub fn connect_all(devices: &[DeviceInfo]) -> Result<Vec<Lamp>, InitErr> {
+    connect_all_with(devices, &DiscoveryOptions::default())
+}
+
+pub fn connect_all_with(
+    devices: &[DeviceInfo],
+    opts: &DiscoveryOptions,
+) -> Result<Vec<Lamp>, InitErr> {
+    let socket = opts.bind()?;
+    let mut lamps = Vec::with_capacity(devices.len());
+    for info in devices {
+        lamps.push(open(socket.try_clone()?, info.ip, opts)?);
     }
+    Ok(lamps)
+}
 
-    let json: Value = serde_json::from_slice(&trim).unwrap();
+fn open(socket: UdpSocket, ip: Ipv4Addr, opts: &DiscoveryOptions) -> Result<Lamp, InitErr> {
+    socket.set_read_timeout(Some(opts.read_timeout))?;
+    let addr = SocketAddrV4::new(ip, opts.cmd_port);
+    let init = dev_status(&socket, &addr)?;
+
+    Ok(Lamp::new(socket, addr, init))
+}
+
+// returns Lamp struct for the first device to respond
+pub fn init() -> Result<Lamp, InitErr> {
+    init_with(&DiscoveryOptions::default())
+}
 
-    json
+pub fn init_with(opts: &DiscoveryOptions) -> Result<Lamp, InitErr> {
+    let devices = discover_with(opts)?;
+    match devices.first() {
+        Some(info) => connect_with(info, opts),
+        None => Err(InitErr::NotFoundErr),
+    }
+}
+
+// get lamp status
+fn dev_status(socket: &UdpSocket, addr: &SocketAddrV4) -> Result<State, CmdErr> {
+    socket.send_to(&Request::DevStatus {}.to_vec()?, addr)?;
+
+    // the socket may be shared by several lamps and late scan replies land on the same
+    // port, skip anything that isn't this lamp's status
+    let mut buf = [0u8; 1024];
+    loop {
+        let (len, src) = socket
+            .recv_from(&mut buf)
+            .map_err(|err| CmdErr::RecvErr(err.kind()))?;
+        if src.ip() != IpAddr::V4(*addr.ip()) {
+            continue;
+        }
+        match Response::parse(&buf[..len])? {
+            Response::DevStatus(status) => return State::try_from(status),
+            Response::Scan(_) => continue,
+        }
+    }
 }
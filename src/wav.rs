@@ -0,0 +1,119 @@
+use hound::{SampleFormat, WavReader};
+use std::{
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{audproc::AudioSource, LampErr, RATE};
+
+impl From<hound::Error> for LampErr {
+    fn from(_: hound::Error) -> Self {
+        LampErr::WavErr
+    }
+}
+
+// how fast frames are handed out
+#[derive(Debug, Clone, Copy)]
+pub enum Playback {
+    RealTime,
+    Fast,
+}
+
+// decoded wav file, downmixed to mono and resampled to RATE
+pub struct Wav {
+    samples: Vec<f32>,
+    pos: usize,
+    playback: Playback,
+    start: Option<Instant>,
+    sent: usize,
+}
+
+impl Wav {
+    pub fn open<P: AsRef<Path>>(path: P, playback: Playback) -> Result<Self, LampErr> {
+        let mut reader = WavReader::open(path)?;
+        let spec = reader.spec();
+
+        let interleaved = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+            SampleFormat::Int => {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / scale))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+
+        // downmix
+        let channels = spec.channels as usize;
+        if channels == 0 {
+            return Err(LampErr::WavErr);
+        }
+        let mono: Vec<f32> = interleaved
+            .chunks(channels)
+            .map(|c| c.iter().sum::<f32>() / channels as f32)
+            .collect();
+
+        Ok(Wav {
+            samples: resample(mono, spec.sample_rate),
+            pos: 0,
+            playback,
+            start: None,
+            sent: 0,
+        })
+    }
+
+    // length in samples at RATE
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+impl AudioSource for Wav {
+    fn frame(&mut self, len: usize) -> Result<Option<Vec<f32>>, LampErr> {
+        if self.pos >= self.samples.len() {
+            return Ok(None);
+        }
+
+        // hold each frame back until it would have been captured live
+        if let Playback::RealTime = self.playback {
+            let start = *self.start.get_or_insert_with(Instant::now);
+            let due = start + Duration::from_secs_f64(self.sent as f64 / RATE as f64);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        let end = (self.pos + len).min(self.samples.len());
+        let mut data = self.samples[self.pos..end].to_vec();
+        data.resize(len, 0.0);
+        self.pos = end;
+        self.sent += len;
+        Ok(Some(data))
+    }
+}
+
+// linear resampling to RATE
+fn resample(samples: Vec<f32>, rate: u32) -> Vec<f32> {
+    if rate == RATE || samples.is_empty() {
+        return samples;
+    }
+    let step = rate as f64 / RATE as f64;
+    let len = (samples.len() as f64 / step) as usize;
+    let mut out = Vec::with_capacity(len);
+    for i in 0..len {
+        let pos = i as f64 * step;
+        let index = pos as usize;
+        let frac = (pos - index as f64) as f32;
+        let a = samples[index];
+        let b = *samples.get(index + 1).unwrap_or(&a);
+        out.push(a + (b - a) * frac);
+    }
+    out
+}
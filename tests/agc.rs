@@ -0,0 +1,86 @@
+use lamper::agc::{self, Agc, AgcOpts};
+
+const FPS: f32 = 20.0;
+
+// feed a level for secs seconds, returning every brightness
+fn hold(agc: &mut Agc, level: f32, secs: f32) -> Vec<u8> {
+    (0..(secs * FPS) as usize)
+        .map(|_| agc.process(level))
+        .collect()
+}
+
+// feed a linear ramp in dB from one level to another over secs seconds
+fn ramp(agc: &mut Agc, from: f32, to: f32, secs: f32) -> Vec<u8> {
+    let frames = (secs * FPS) as usize;
+    (0..frames)
+        .map(|i| agc.process(from + (to - from) * i as f32 / frames as f32))
+        .collect()
+}
+
+#[test]
+fn steady_level_sits_at_target() {
+    let mut agc = Agc::new(AgcOpts::default(), FPS);
+    let out = hold(&mut agc, -20.0, 5.0);
+    assert!(out.iter().all(|b| *b == 80));
+}
+
+#[test]
+fn gated_levels_are_off_and_hold_the_reference() {
+    let mut agc = Agc::new(AgcOpts::default(), FPS);
+    hold(&mut agc, -20.0, 1.0);
+    let out = hold(&mut agc, -90.0, 10.0);
+    assert!(out.iter().all(|b| *b == 0));
+    assert_eq!(agc.reference(), Some(-20.0));
+}
+
+#[test]
+fn slow_fade_is_compensated() {
+    let mut agc = Agc::new(AgcOpts::default(), FPS);
+    hold(&mut agc, -10.0, 1.0);
+    // 30 dB over a minute is slow next to the 3s release
+    let out = ramp(&mut agc, -10.0, -40.0, 60.0);
+    let last = *out.last().unwrap();
+    assert!((75..=80).contains(&last), "brightness {}", last);
+}
+
+#[test]
+fn loud_step_flashes_then_settles() {
+    let mut agc = Agc::new(AgcOpts::default(), FPS);
+    hold(&mut agc, -40.0, 5.0);
+    let out = hold(&mut agc, -10.0, 2.0);
+    assert_eq!(out[0], 100);
+    // 50ms attack settles well within a second
+    assert!(out[(FPS as usize)..].iter().all(|b| *b == 80));
+}
+
+#[test]
+fn quiet_step_dips_then_recovers() {
+    let mut agc = Agc::new(AgcOpts::default(), FPS);
+    hold(&mut agc, -10.0, 5.0);
+    let out = hold(&mut agc, -25.0, 20.0);
+    // half the 30 dB range below the reference, less the first frame of release
+    assert!((30..=31).contains(&out[0]), "brightness {}", out[0]);
+    assert!(out.windows(2).all(|w| w[1] >= w[0]));
+    assert!(*out.last().unwrap() >= 79);
+}
+
+#[test]
+fn louder_frames_are_brighter() {
+    let mut agc = Agc::new(AgcOpts::default(), FPS);
+    hold(&mut agc, -30.0, 5.0);
+    let quiet = agc.process(-35.0);
+    let mut agc = Agc::new(AgcOpts::default(), FPS);
+    hold(&mut agc, -30.0, 5.0);
+    let loud = agc.process(-32.0);
+    assert!(loud > quiet);
+}
+
+#[test]
+fn full_scale_sine_is_zero_db() {
+    let sine: Vec<f32> = (0..4096)
+        .map(|i| (std::f32::consts::TAU * 100.0 * i as f32 / 4096.0).sin())
+        .collect();
+    assert!(agc::level(&sine).abs() < 0.01);
+    let half: Vec<f32> = sine.iter().map(|x| x * 0.5).collect();
+    assert!((agc::level(&half) + 6.02).abs() < 0.01);
+}
@@ -0,0 +1,119 @@
+mod mock;
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use futures_util::{future, StreamExt};
+use lamper::{
+    aio::Hub,
+    udp::{Cmd, CmdErr, DiscoveryOptions, InitErr},
+};
+use mock::{Faults, MockLamp, MockOpts};
+use tokio::runtime::Runtime;
+
+fn opts() -> DiscoveryOptions {
+    DiscoveryOptions {
+        timeout: Duration::from_millis(300),
+        read_timeout: Duration::from_millis(300),
+        ..DiscoveryOptions::default()
+    }
+}
+
+// the port lock is taken outside the runtime so it's never held across an await
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    Runtime::new().unwrap().block_on(fut)
+}
+
+#[test]
+fn discover_streams_the_lamp() {
+    let _ports = mock::lock();
+    let _mock = MockLamp::start(MockOpts::default());
+
+    let devices: Vec<_> = block_on(async {
+        let hub = Hub::bind(opts()).await.unwrap();
+        hub.discover().collect().await
+    });
+    assert_eq!(devices.len(), 1);
+    let info = devices[0].as_ref().unwrap();
+    assert_eq!(info.mac, "C5:32:32:36:72:4E");
+    assert_eq!(info.ip, Ipv4Addr::LOCALHOST);
+}
+
+#[test]
+fn commands_and_status() {
+    let _ports = mock::lock();
+    let opts = MockOpts::default();
+    let mock = MockLamp::start(opts.clone());
+
+    block_on(async {
+        let hub = Hub::bind(self::opts()).await.unwrap();
+        let lamp = hub.connect(Ipv4Addr::LOCALHOST).await.unwrap();
+        assert_eq!(lamp.init, opts.state);
+
+        lamp.send_cmd(Cmd::Brightness(30)).await.unwrap();
+        lamp.send_cmd(Cmd::Color([1, 2, 3])).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let state = lamp.status().await.unwrap();
+        assert_eq!(state.bright, 30);
+        assert_eq!(state.color, [1, 2, 3]);
+        assert!(matches!(
+            lamp.send_cmd(Cmd::Brightness(101)).await,
+            Err(CmdErr::InvalidBrightnessErr(101))
+        ));
+    });
+    assert_eq!(mock.state().bright, 30);
+}
+
+#[test]
+fn concurrent_status_replies_reach_their_lamp() {
+    let _ports = mock::lock();
+    let _mock = MockLamp::start(MockOpts::default());
+
+    block_on(async {
+        let hub = Hub::bind(opts()).await.unwrap();
+        let lamp = hub.connect(Ipv4Addr::LOCALHOST).await.unwrap();
+        // nothing answers here, it must not hold up or steal the other replies
+        let missing = hub.connect(Ipv4Addr::new(127, 0, 0, 3));
+        let checks = future::join_all((0..8).map(|_| lamp.status()));
+        let (missing, checks) = future::join(missing, checks).await;
+        assert!(matches!(
+            missing,
+            Err(InitErr::DevStatusErr(CmdErr::RecvErr(_)))
+        ));
+        assert!(checks.iter().all(|res| res.is_ok()));
+    });
+}
+
+#[test]
+fn status_errors() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+
+    block_on(async {
+        let hub = Hub::bind(opts()).await.unwrap();
+        let lamp = hub.connect(Ipv4Addr::LOCALHOST).await.unwrap();
+
+        mock.set_faults(Faults {
+            loss: 1.0,
+            ..Faults::default()
+        });
+        assert!(matches!(lamp.check().await, Err(CmdErr::RecvErr(_))));
+        mock.set_faults(Faults {
+            garbage: 1.0,
+            ..Faults::default()
+        });
+        assert!(matches!(lamp.check().await, Err(CmdErr::SerdeErr(_))));
+        mock.set_faults(Faults::default());
+        assert!(lamp.check().await.is_ok());
+    });
+}
+
+#[test]
+fn taken_port_is_an_error() {
+    let _ports = mock::lock();
+    let _taken = std::net::UdpSocket::bind("0.0.0.0:4002").unwrap();
+
+    assert!(matches!(
+        block_on(Hub::bind(opts())),
+        Err(InitErr::BindErr { .. })
+    ));
+}
@@ -0,0 +1,182 @@
+use std::{
+    sync::{mpsc, Arc, RwLock},
+    thread,
+};
+
+use lamper::{
+    audproc::{self, AudioSource, Ring},
+    colproc::{self, Band, Beats, Mode, Update},
+    health::Status,
+    synth::{Signal, Synth},
+    wav::{Playback, Wav},
+    Frames,
+};
+
+const TONE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tone.wav");
+
+// run a source through audproc and colproc on the full band and collect count frames
+fn updates<S: AudioSource + Send + 'static>(source: S, mode: Mode, count: usize) -> Vec<Update> {
+    let frames = Frames::default();
+    let status = Arc::new(RwLock::new(Status::Connected));
+    let (aptx, aprx) = mpsc::channel();
+    let (cptx, cprx) = mpsc::channel();
+    let ap = {
+        let status = Arc::clone(&status);
+        thread::spawn(move || audproc::start(aptx, status, source, frames))
+    };
+    let cp = {
+        let status = Arc::clone(&status);
+        let mode = Arc::new(RwLock::new(mode));
+        thread::spawn(move || {
+            colproc::process_bands(aprx, cptx, status, mode, vec![Band::FULL], frames)
+        })
+    };
+    let out: Vec<Update> = cprx.iter().take(count).map(|frame| frame[0]).collect();
+    *status.write().unwrap() = Status::Stopped;
+    drop(cprx);
+    // whichever thread stops first closes the channel on the other
+    let _ = ap.join().unwrap();
+    let _ = cp.join().unwrap();
+    out
+}
+
+fn color(update: Update) -> [u8; 3] {
+    match update {
+        Update::Both(_, rgb) | Update::Color(rgb) => rgb,
+        Update::Brightness(_) => panic!("expected a color in {:?}", update),
+    }
+}
+
+#[test]
+fn sine_color_follows_pitch() {
+    let low = updates(Synth::new(Signal::Sine(60.0), 0.5), Mode::Spectrum, 40);
+    let high = updates(Synth::new(Signal::Sine(10000.0), 0.5), Mode::Spectrum, 40);
+    assert_eq!(low.len(), 40);
+
+    // low end of the wheel is red, the high end blue to violet
+    let [r, _, b] = color(*low.last().unwrap());
+    assert!(r == 255 && b < 100, "low sine gave {:?}", [r, b]);
+    let [r, _, b] = color(*high.last().unwrap());
+    assert!(b == 255 && r < 200, "high sine gave {:?}", [r, b]);
+    // a steady tone settles to a lit lamp
+    assert!(matches!(low.last(), Some(Update::Both(bright, _)) if *bright > 0));
+}
+
+#[test]
+fn sweep_moves_across_the_wheel() {
+    let sweep = Signal::Sweep {
+        from: 50.0,
+        to: 12000.0,
+        secs: 2.0,
+    };
+    // a bit under 2s of frames, one pass of the sweep
+    let out = updates(Synth::new(sweep, 0.5), Mode::Spectrum, 160);
+    let first = color(out[20]);
+    let last = color(out[150]);
+    assert!(first[0] > first[2], "start of the sweep gave {:?}", first);
+    assert!(last[2] > last[0], "end of the sweep gave {:?}", last);
+}
+
+#[test]
+fn wav_fixture_is_downmixed_and_resampled() {
+    // 0.25s stereo at 22050hz, a half scale 1khz tone on both channels
+    let mut wav = Wav::open(TONE, Playback::Fast).unwrap();
+    assert_eq!(wav.len(), 11024);
+    let data = wav.frame(4096).unwrap().unwrap();
+    let peak = data.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+    assert!((peak - 0.5).abs() < 0.01, "peak {}", peak);
+
+    // 22 hops of 512, the last padded, windows start once 8 have filled the ring
+    let wav = Wav::open(TONE, Playback::Fast).unwrap();
+    let (tx, rx) = mpsc::channel();
+    let status = Arc::new(RwLock::new(Status::Connected));
+    audproc::start(tx, status, wav, Frames::default()).unwrap();
+    let windows: Vec<Vec<f32>> = rx.iter().collect();
+    assert_eq!(windows.len(), 15);
+    assert!(windows.iter().all(|window| window.len() == 4096));
+    assert!(Wav::open("tests/data/missing.wav", Playback::Fast).is_err());
+}
+
+#[test]
+fn beat_mode_flashes_on_clicks() {
+    let palette = vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+    // 8s of 120bpm clicks, onsets start once 4s of history has built up
+    let frames = (8.0 * Frames::default().fps()) as usize;
+    let out = updates(
+        Synth::new(Signal::Clicks(120.0), 0.8),
+        Mode::Beat {
+            palette: palette.clone(),
+        },
+        frames,
+    );
+    let flashes: Vec<[u8; 3]> = out
+        .iter()
+        .filter_map(|update| match update {
+            Update::Both(100, rgb) => Some(*rgb),
+            _ => None,
+        })
+        .collect();
+    assert!(
+        (6..=10).contains(&flashes.len()),
+        "{} flashes",
+        flashes.len()
+    );
+    assert!(flashes.iter().all(|rgb| palette.contains(rgb)));
+    assert!(flashes.windows(2).all(|pair| pair[0] != pair[1]));
+}
+
+#[test]
+fn ring_windows_overlap_and_wrap() {
+    // window of 8 with a hop of 3, samples count up so positions are easy to check
+    let mut ring = Ring::new(8);
+    let mut next = 0.0;
+    let mut hop = || {
+        let data: Vec<f32> = (0..3).map(|i| next + i as f32).collect();
+        next += 3.0;
+        data
+    };
+    ring.push(&hop());
+    ring.push(&hop());
+    assert!(!ring.is_full());
+    ring.push(&hop());
+    assert!(ring.is_full());
+    assert_eq!(ring.window(), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+
+    // each hop drops the oldest 3 and wraps around the end of the buffer
+    ring.push(&hop());
+    assert_eq!(ring.window(), [4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0]);
+    for _ in 0..5 {
+        ring.push(&hop());
+    }
+    let expected: Vec<f32> = (19..27).map(|x| x as f32).collect();
+    assert_eq!(ring.window(), expected);
+
+    // a push longer than the ring keeps only its tail
+    ring.push(&(100..110).map(|x| x as f32).collect::<Vec<f32>>());
+    let expected: Vec<f32> = (102..110).map(|x| x as f32).collect();
+    assert_eq!(ring.window(), expected);
+}
+
+#[test]
+fn every_mode_runs_through_process_bands() {
+    let palette = vec![[255, 0, 0], [0, 0, 255]];
+    let brightness = Mode::Brightness {
+        band: Band::BASS,
+        colors: palette.clone(),
+    };
+    let out = updates(Synth::new(Signal::Sine(80.0), 0.5), brightness, 40);
+    assert!(out
+        .iter()
+        .all(|update| matches!(update, Update::Both(_, rgb) if palette.contains(rgb))));
+
+    // cycle mode sets a color first then follows the volume until a change is due
+    let cycle = Mode::Cycle {
+        beats: Beats::Four,
+        palette: palette.clone(),
+    };
+    let out = updates(Synth::new(Signal::Noise, 0.5), cycle, 40);
+    assert!(matches!(out[0], Update::Color(rgb) if palette.contains(&rgb)));
+    assert!(out[1..]
+        .iter()
+        .all(|update| matches!(update, Update::Brightness(_))));
+}
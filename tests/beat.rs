@@ -0,0 +1,54 @@
+use lamper::{
+    audproc::{AudioSource, Ring},
+    beat::{Onset, Tempo},
+    spectrum::{Spectrum, Window},
+    synth::{Signal, Synth},
+    Frames,
+};
+
+// run a click track through the onset detector and tempo tracker for secs, returns
+// the tracker and the frames beats landed on
+fn track(bpm: f32, secs: f32) -> (Tempo, Vec<usize>) {
+    let frames = Frames::default();
+    let mut synth = Synth::new(Signal::Clicks(bpm), 0.8);
+    let mut ring = Ring::new(frames.window);
+    let mut spectrum = Spectrum::new(frames.window, Window::Hann);
+    let mut onset = Onset::new(frames.fps());
+    let mut tempo = Tempo::new(frames.fps());
+    let mut beats = Vec::new();
+    for frame in 0..(secs * frames.fps()) as usize {
+        ring.push(&synth.frame(frames.hop).unwrap().unwrap());
+        onset.detect(spectrum.magnitude(&ring.window()));
+        if tempo.update(onset.flux()) {
+            beats.push(frame);
+        }
+    }
+    (tempo, beats)
+}
+
+#[test]
+fn click_track_tempo_and_beats() {
+    let fps = Frames::default().fps();
+    let (tempo, beats) = track(120.0, 20.0);
+    let bpm = tempo.bpm().unwrap();
+    assert!((bpm - 120.0).abs() < 3.0, "bpm {}", bpm);
+    assert!((0.0..1.0).contains(&tempo.phase()));
+
+    // once locked, beats are half a second apart give or take a couple of frames
+    let late: Vec<usize> = beats
+        .into_iter()
+        .filter(|b| *b as f32 > 12.0 * fps)
+        .collect();
+    assert!(late.len() >= 14, "{} beats in the last 8s", late.len());
+    for pair in late.windows(2) {
+        let gap = (pair[1] - pair[0]) as f32 / fps;
+        assert!((gap - 0.5).abs() < 0.03, "beats {:.3}s apart", gap);
+    }
+}
+
+#[test]
+fn slower_click_track() {
+    let (tempo, _) = track(90.0, 20.0);
+    let bpm = tempo.bpm().unwrap();
+    assert!((bpm - 90.0).abs() < 3.0, "bpm {}", bpm);
+}
@@ -0,0 +1,68 @@
+use lamper::bright::{BrightMap, Curve};
+
+#[test]
+fn default_is_unchanged() {
+    let map = BrightMap::default();
+    for level in 0..=100 {
+        assert_eq!(map.map(level), level);
+    }
+}
+
+#[test]
+fn levels_land_between_min_and_max() {
+    let map = BrightMap {
+        min: 20,
+        max: 80,
+        ..BrightMap::default()
+    };
+    assert_eq!(map.map(0), 0);
+    assert_eq!(map.map(1), 21);
+    assert_eq!(map.map(50), 50);
+    assert_eq!(map.map(100), 80);
+    assert_eq!(map.map(255), 80);
+}
+
+#[test]
+fn floor_keeps_the_lamp_lit() {
+    let map = BrightMap {
+        max: 60,
+        floor: 10,
+        ..BrightMap::default()
+    };
+    assert_eq!(map.map(0), 10);
+    assert_eq!(map.map(5), 10);
+    assert_eq!(map.map(100), 60);
+    // a floor above the ceiling is held at it
+    let map = BrightMap { floor: 90, ..map };
+    assert_eq!(map.map(0), 60);
+}
+
+#[test]
+fn curves_bend_the_middle() {
+    let linear = BrightMap::default();
+    let gamma = BrightMap {
+        curve: Curve::Gamma(2.0),
+        ..linear
+    };
+    let log = BrightMap {
+        curve: Curve::Log,
+        ..linear
+    };
+    assert_eq!(gamma.map(50), 25);
+    assert!(log.map(50) > linear.map(50));
+    for map in [gamma, log] {
+        assert_eq!(map.map(100), 100);
+        let levels: Vec<u8> = (0..=100).map(|level| map.map(level)).collect();
+        assert!(levels.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
+
+#[test]
+fn curve_names() {
+    assert_eq!(Curve::parse("linear"), Some(Curve::Linear));
+    assert_eq!(Curve::parse("log"), Some(Curve::Log));
+    assert_eq!(Curve::parse("gamma"), Some(Curve::Gamma(Curve::GAMMA)));
+    assert_eq!(Curve::parse("gamma=1.8"), Some(Curve::Gamma(1.8)));
+    assert_eq!(Curve::parse("gamma=0"), None);
+    assert_eq!(Curve::parse("cubic"), None);
+}
@@ -0,0 +1,44 @@
+use std::{env, fs, net::Ipv4Addr, process};
+
+use lamper::{cache::LampCache, udp::DeviceInfo};
+
+fn lamp(mac: &str, ip: [u8; 4]) -> DeviceInfo {
+    DeviceInfo {
+        mac: mac.to_string(),
+        ..DeviceInfo::from_ip(ip.into())
+    }
+}
+
+#[test]
+fn lamps_are_found_again_after_a_reload() {
+    let dir = env::temp_dir().join(format!("lamper-cache-{}", process::id()));
+    let path = dir.join("lamper").join("lamps.json");
+
+    let mut cache = LampCache::new();
+    cache.insert(&lamp("c5:32:32:36:72:4e", [192, 168, 1, 20]));
+    // the lamp got a new address from dhcp
+    cache.insert(&lamp("C5:32:32:36:72:4E", [192, 168, 1, 21]));
+    cache.insert(&lamp("AA:BB:CC:DD:EE:FF", [192, 168, 1, 30]));
+    cache.save(&path).unwrap();
+
+    let cache = LampCache::load(&path);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(
+        cache.get("c5:32:32:36:72:4e").unwrap().ip,
+        Ipv4Addr::new(192, 168, 1, 21)
+    );
+    assert_eq!(
+        cache.find_ip([192, 168, 1, 30].into()).unwrap().mac,
+        "AA:BB:CC:DD:EE:FF"
+    );
+    assert!(cache.find_ip([192, 168, 1, 20].into()).is_none());
+}
+
+#[test]
+fn missing_cache_is_empty() {
+    let path = env::temp_dir()
+        .join("lamper-no-such-dir")
+        .join("lamps.json");
+    assert!(LampCache::load(&path).is_empty());
+}
@@ -0,0 +1,243 @@
+mod mock;
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use lamper::{
+    cli::Target,
+    cloud::{Client, CloudDevice, CloudOpts},
+    colproc::Update,
+    group::{LampGroup, MemberOpts},
+    sched::{SchedOpts, Scheduler},
+    udp::{Cmd, CmdErr, Light, State, Turn},
+};
+use mock::http::{ApiOpts, MockApi, DEVICE, KEY, MODEL};
+
+fn client(api: &MockApi, key: &str) -> Client {
+    Client::with_opts(
+        key,
+        CloudOpts {
+            url: api.url.clone(),
+            timeout: Duration::from_secs(5),
+            ..CloudOpts::default()
+        },
+    )
+    .unwrap()
+}
+
+#[test]
+fn lists_devices_and_reads_state() {
+    let opts = ApiOpts::default();
+    let api = MockApi::start(opts.clone());
+    let client = client(&api, KEY);
+
+    let devices = client.devices().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].device, DEVICE);
+    assert_eq!(devices[0].model, MODEL);
+    assert!(devices[0].support_cmds.iter().any(|cmd| cmd == "colorTem"));
+
+    let lamp = client.connect(&devices[0]).unwrap();
+    assert_eq!(*lamp.init(), opts.state);
+    assert!(api.requests()[1].path.contains("model=H6159"));
+}
+
+#[test]
+fn commands_go_through_the_light_interface() {
+    let opts = ApiOpts::default();
+    let api = MockApi::start(opts.clone());
+    let client = client(&api, KEY);
+    let info = client.devices().unwrap().remove(0);
+    let lamp: Box<dyn Light> = Box::new(client.connect(&info).unwrap());
+
+    lamp.send_cmd(Cmd::Brightness(80)).unwrap();
+    lamp.send_cmd(Cmd::Temp(3000)).unwrap();
+    assert_eq!(api.state().bright, 80);
+    assert_eq!(lamp.status().unwrap().temp, 3000);
+    let requests = api.requests();
+    let control = &requests[3].body;
+    assert_eq!(control["cmd"]["name"], "colorTem");
+    assert_eq!(control["cmd"]["value"], 3000);
+
+    lamp.restore().unwrap();
+    assert_eq!(api.state(), opts.state);
+    assert!(matches!(
+        lamp.send_cmd(Cmd::Brightness(101)),
+        Err(CmdErr::InvalidBrightnessErr(101))
+    ));
+}
+
+#[test]
+fn powered_off_restore_turns_off_last() {
+    let state = State {
+        pwr: Turn::Off,
+        bright: 10,
+        color: [255, 0, 0],
+        temp: 0,
+    };
+    let api = MockApi::start(ApiOpts {
+        state: state.clone(),
+        ..ApiOpts::default()
+    });
+    let client = client(&api, KEY);
+    let lamp = client.connect(&client.devices().unwrap()[0]).unwrap();
+    lamp.send_cmd(Cmd::OnOff(Turn::On)).unwrap();
+
+    lamp.restore().unwrap();
+    assert_eq!(api.state(), state);
+    let names: Vec<String> = api
+        .requests()
+        .iter()
+        .filter(|req| req.method == "PUT")
+        .map(|req| req.body["cmd"]["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["turn", "brightness", "color", "turn"]);
+}
+
+#[test]
+fn unsupported_commands_are_refused_locally() {
+    let api = MockApi::start(ApiOpts {
+        support_cmds: vec!["turn", "brightness"],
+        ..ApiOpts::default()
+    });
+    let client = client(&api, KEY);
+    let lamp = client.connect(&client.devices().unwrap()[0]).unwrap();
+
+    assert!(matches!(
+        lamp.send_cmd(Cmd::Color([1, 2, 3])),
+        Err(CmdErr::UnsupportedErr("color"))
+    ));
+    assert_eq!(api.requests().len(), 2);
+}
+
+#[test]
+fn quota_is_respected() {
+    let api = MockApi::start(ApiOpts {
+        quota: Some(3),
+        reset: 120,
+        ..ApiOpts::default()
+    });
+    let client = client(&api, KEY);
+    let lamp = client.connect(&client.devices().unwrap()[0]).unwrap();
+
+    // the third request uses up the quota, the headers say so and nothing more is sent
+    lamp.send_cmd(Cmd::Brightness(5)).unwrap();
+    let wait = match lamp.send_cmd(Cmd::Brightness(6)) {
+        Err(CmdErr::RateLimitErr(wait)) => wait,
+        res => panic!("expected a rate limit, got {:?}", res),
+    };
+    assert!(wait > Duration::from_secs(100) && wait <= Duration::from_secs(120));
+    assert_eq!(api.requests().len(), 3);
+    assert!(client.limited().is_some());
+}
+
+#[test]
+fn each_device_is_paced() {
+    let api = MockApi::start(ApiOpts::default());
+    let client = Client::with_opts(
+        KEY,
+        CloudOpts {
+            url: api.url.clone(),
+            per_minute: 3,
+            ..CloudOpts::default()
+        },
+    )
+    .unwrap();
+    let info = client.devices().unwrap().remove(0);
+    let lamp = client.connect(&info).unwrap();
+
+    // reading the state on connect was the first of three this minute
+    lamp.send_cmd(Cmd::Brightness(10)).unwrap();
+    assert!(client.paced(&info).is_none());
+    lamp.send_cmd(Cmd::Brightness(20)).unwrap();
+    let wait = match lamp.send_cmd(Cmd::Brightness(30)) {
+        Err(CmdErr::RateLimitErr(wait)) => wait,
+        res => panic!("expected pacing, got {:?}", res),
+    };
+    assert!(wait > Duration::from_secs(55) && wait <= Duration::from_secs(60));
+    assert!(client.paced(&info).is_some());
+    assert!(matches!(lamp.status(), Err(CmdErr::RateLimitErr(_))));
+    // the api never saw the refused requests and the account isn't limited
+    assert_eq!(api.requests().len(), 4);
+    assert_eq!(api.state().bright, 20);
+    assert!(client.limited().is_none());
+
+    // other devices on the account have their own allowance
+    let other = CloudDevice {
+        device: "11:22:33:44:55:66:77:88".to_string(),
+        ..info.clone()
+    };
+    client.control(&other, Cmd::Brightness(40)).unwrap();
+    assert_eq!(api.state().bright, 40);
+}
+
+#[test]
+fn too_many_requests_holds_off() {
+    let api = MockApi::start(ApiOpts {
+        quota: Some(0),
+        ..ApiOpts::default()
+    });
+    let client = client(&api, KEY);
+
+    assert!(matches!(client.devices(), Err(CmdErr::RateLimitErr(_))));
+    assert!(client.limited().is_some());
+}
+
+#[test]
+fn api_errors() {
+    let api = MockApi::start(ApiOpts::default());
+
+    assert!(matches!(
+        client(&api, "wrong").devices(),
+        Err(CmdErr::ApiErr { code: 401, .. })
+    ));
+
+    let client = client(&api, KEY);
+    let lamp = client.connect(&client.devices().unwrap()[0]).unwrap();
+    api.set_online(false);
+    assert!(matches!(lamp.check(), Err(CmdErr::OfflineErr)));
+
+    let gone = Client::with_opts(
+        KEY,
+        CloudOpts {
+            url: "http://127.0.0.1:9".to_string(),
+            timeout: Duration::from_secs(1),
+            ..CloudOpts::default()
+        },
+    )
+    .unwrap();
+    assert!(matches!(gone.devices(), Err(CmdErr::HttpErr(_))));
+}
+
+#[test]
+fn group_and_scheduler_drive_cloud_lamps() {
+    let opts = ApiOpts::default();
+    let api = MockApi::start(opts.clone());
+    let client = client(&api, KEY);
+    let info = client.devices().unwrap().remove(0);
+    // the device id names the lamp the same way the lan mac does
+    assert_eq!(info.mac(), "CC:DD:EE:FF:00:11");
+    let target = Target::parse(DEVICE).unwrap();
+    assert!(target.matches(&Ipv4Addr::UNSPECIFIED, &info.mac()));
+
+    let mut group = LampGroup::new();
+    group.add(
+        client.connect(&info).unwrap(),
+        MemberOpts {
+            maxb: 50,
+            ..MemberOpts::default()
+        },
+    );
+    group.turn(Turn::On).unwrap();
+    let mut sched = Scheduler::new(group, SchedOpts::per_minute(10));
+
+    // one command at a time, the color waits for the next slot a few seconds on
+    sched.push(vec![Update::Both(100, [255, 0, 0])]);
+    sched.flush().unwrap();
+    assert_eq!(api.state().bright, 50);
+    assert_eq!(api.state().color, opts.state.color);
+    assert!(sched.wait().unwrap() > Duration::from_secs(5));
+    assert_eq!(sched.metrics().sent, 1);
+
+    sched.group().restore().unwrap();
+    assert_eq!(api.state(), opts.state);
+}
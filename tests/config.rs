@@ -0,0 +1,359 @@
+use std::path::PathBuf;
+
+use lamper::{
+    bright::{BrightMap, Curve},
+    cli::{Args, ModeKind, Retry, Target},
+    colproc::{self, Band, Beats, Mode},
+    config::{Config, ConfigErr},
+    synth::Signal,
+    Frames,
+};
+
+const CONFIG: &str = r#"
+default = "quiet"
+
+[profiles.party]
+devices = ["192.168.1.20", "aa:bb:cc:dd:ee:ff:00:11"]
+api_key = "profile-key"
+source = "monitor"
+mode = "cycle"
+beats = 2
+palette = [[255, 0, 0], [0, 0, 255]]
+min_frequency = 40.0
+max_frequency = 8000.0
+window = 2048
+hop = 1024
+cmd_delay = 60
+max_brightness = 80
+min_brightness = 10
+floor = 5
+curve = "log"
+retry = "always"
+
+[profiles.quiet]
+max_brightness = 20
+
+[profiles.file]
+wav = "tests/data/tone.wav"
+
+[profiles.signal]
+synth = "sweep=100-5000"
+"#;
+
+#[test]
+fn profile_sets_everything() {
+    let config = Config::parse(CONFIG).unwrap();
+    let args = config
+        .profile(Some("party"))
+        .unwrap()
+        .unwrap()
+        .args()
+        .unwrap();
+    assert_eq!(
+        args.devices,
+        vec![
+            Target::Ip([192, 168, 1, 20].into()),
+            Target::Mac("CC:DD:EE:FF:00:11".to_string())
+        ]
+    );
+    assert_eq!(args.source.as_deref(), Some("monitor"));
+    assert_eq!(args.api_key.as_deref(), Some("profile-key"));
+    assert_eq!(
+        args.mode(),
+        Some(Mode::Cycle {
+            beats: Beats::Two,
+            palette: vec![[255, 0, 0], [0, 0, 255]]
+        })
+    );
+    assert_eq!(
+        args.range,
+        Some(Band {
+            min: 40.0,
+            max: 8000.0
+        })
+    );
+    assert_eq!(args.window, Some(2048));
+    assert_eq!(args.hop, Some(1024));
+    let frames = args.frames().unwrap();
+    assert_eq!((frames.window, frames.hop), (2048, 1024));
+    assert_eq!(args.delay, Some(60));
+    assert_eq!(args.maxb, Some(80));
+    assert_eq!(
+        args.bright(80),
+        BrightMap {
+            min: 10,
+            max: 80,
+            floor: 5,
+            curve: Curve::Log
+        }
+    );
+    assert_eq!(args.retry, Some(Retry::Always));
+
+    // the other audio inputs
+    let input = |name: &str| config.profile(Some(name)).unwrap().unwrap().args().unwrap();
+    let args = input("file");
+    assert_eq!(args.wav, Some(PathBuf::from("tests/data/tone.wav")));
+    assert_eq!((args.source, args.synth), (None, None));
+    assert_eq!(
+        input("signal").synth,
+        Some(Signal::Sweep {
+            from: 100.0,
+            to: 5000.0,
+            secs: 10.0
+        })
+    );
+    // an input given as a flag replaces the profile's
+    let flags = Args::try_parse_from(["lamper", "-s", "default"]).unwrap();
+    let args = flags.or(input("file"));
+    assert_eq!(args.source.as_deref(), Some("default"));
+    assert_eq!(args.wav, None);
+}
+
+#[test]
+fn default_profile_and_missing_profile() {
+    let config = Config::parse(CONFIG).unwrap();
+    let args = config.profile(None).unwrap().unwrap().args().unwrap();
+    assert_eq!(args.maxb, Some(20));
+    assert!(matches!(
+        config.profile(Some("nope")),
+        Err(ConfigErr::ProfileErr)
+    ));
+}
+
+#[test]
+fn flags_override_profile() {
+    let config = Config::parse(CONFIG).unwrap();
+    let profile = config
+        .profile(Some("party"))
+        .unwrap()
+        .unwrap()
+        .args()
+        .unwrap();
+    let flags = Args::try_parse_from([
+        "lamper",
+        "-m",
+        "brightness",
+        "-b",
+        "50",
+        "--curve",
+        "gamma=2",
+        "--api-key",
+        "flag-key",
+    ])
+    .unwrap();
+    let args = flags.or(profile);
+    assert_eq!(args.mode, Some(ModeKind::Brightness));
+    assert_eq!(args.maxb, Some(50));
+    assert_eq!(args.delay, Some(60));
+    assert_eq!(args.curve, Some(Curve::Gamma(2.0)));
+    assert_eq!(args.floor, Some(5));
+    assert_eq!(args.api_key.as_deref(), Some("flag-key"));
+    // palette from the profile carries over to the mode picked on the command line
+    assert_eq!(
+        args.mode(),
+        Some(Mode::Brightness {
+            band: Band::KICK,
+            colors: vec![[255, 0, 0], [0, 0, 255]]
+        })
+    );
+}
+
+#[test]
+fn bad_values_are_rejected() {
+    let bad = |profile: &str| {
+        let config = Config::parse(&format!("[profiles.bad]\n{}", profile)).unwrap();
+        config.profile(Some("bad")).unwrap().unwrap().args().err()
+    };
+    assert!(matches!(
+        bad("window = 1000"),
+        Some(ConfigErr::ValueErr("window"))
+    ));
+    assert!(matches!(
+        bad("window = 128"),
+        Some(ConfigErr::ValueErr("window"))
+    ));
+    assert!(matches!(bad("hop = 128"), Some(ConfigErr::ValueErr("hop"))));
+    // longer than the window
+    assert!(matches!(
+        bad("window = 512\nhop = 1024"),
+        Some(ConfigErr::ValueErr("hop"))
+    ));
+    assert!(matches!(
+        bad("max_frequency = inf"),
+        Some(ConfigErr::ValueErr("max_frequency"))
+    ));
+    assert!(matches!(
+        bad("min_frequency = nan"),
+        Some(ConfigErr::ValueErr("min_frequency"))
+    ));
+    assert!(matches!(
+        bad("synth = \"square\""),
+        Some(ConfigErr::ValueErr("synth"))
+    ));
+    assert!(matches!(
+        bad("wav = \"a.wav\"\nsynth = \"noise\""),
+        Some(ConfigErr::ValueErr("wav"))
+    ));
+    assert!(matches!(
+        bad("beats = 3"),
+        Some(ConfigErr::ValueErr("beats"))
+    ));
+    assert!(matches!(
+        bad("max_brightness = 0"),
+        Some(ConfigErr::ValueErr("max_brightness"))
+    ));
+    assert!(matches!(
+        bad("curve = \"cubic\""),
+        Some(ConfigErr::ValueErr("curve"))
+    ));
+    assert!(matches!(
+        bad("floor = 101"),
+        Some(ConfigErr::ValueErr("floor"))
+    ));
+    assert!(matches!(
+        bad("devices = [\"lamp\"]"),
+        Some(ConfigErr::ValueErr("devices"))
+    ));
+    assert!(matches!(
+        Config::parse("[profiles.bad]\nbrightness = 10"),
+        Err(ConfigErr::ParseErr(_))
+    ));
+
+    // flags are checked the same way, a max past the audible range is held at 20khz
+    assert!(Args::try_parse_from(["lamper", "--range", "20-inf"]).is_err());
+    assert!(Args::try_parse_from(["lamper", "--range", "NaN-100"]).is_err());
+    let args = Args::try_parse_from(["lamper", "--range", "40-30000"]).unwrap();
+    assert_eq!(args.range, Some(Band::new(40.0, 20000.0).unwrap()));
+}
+
+#[test]
+fn tiny_windows_are_rejected() {
+    assert!(Args::try_parse_from(["lamper", "--window", "1"]).is_err());
+    assert!(Args::try_parse_from(["lamper", "--window", "2"]).is_err());
+    let args = Args::try_parse_from(["lamper", "--window", "256"]).unwrap();
+    assert_eq!(args.window, Some(256));
+    assert!(Frames::new(128, 128).is_none());
+    assert!(Frames::new(4096, 1).is_none());
+    assert_eq!(Frames::with_window(256).unwrap().hop, 256);
+}
+
+#[test]
+fn hop_is_set_apart_from_the_window() {
+    let args = Args::try_parse_from(["lamper", "--window", "4096", "--hop", "1024"]).unwrap();
+    let frames = args.frames().unwrap();
+    assert_eq!((frames.window, frames.hop), (4096, 1024));
+    // the hop alone keeps the default window
+    let args = Args::try_parse_from(["lamper", "--hop", "2048"]).unwrap();
+    assert_eq!(args.frames().unwrap().window, Frames::default().window);
+    assert_eq!(Args::default().frames().unwrap().hop, Frames::default().hop);
+
+    assert!(Args::try_parse_from(["lamper", "--hop", "100"]).is_err());
+    assert!(Args::try_parse_from(["lamper", "--hop", "x"]).is_err());
+    // each fits on its own but not together
+    let args = Args::try_parse_from(["lamper", "--window", "512", "--hop", "1024"]).unwrap();
+    assert!(args.frames().is_none());
+}
+
+#[test]
+fn beat_mode_takes_the_palette() {
+    let args = Args::try_parse_from(["lamper", "-m", "beat"]).unwrap();
+    assert_eq!(
+        args.mode(),
+        Some(Mode::Beat {
+            palette: colproc::PALETTE.to_vec()
+        })
+    );
+    let args = Args::try_parse_from(["lamper", "-m", "beat", "-p", "0,0,255"]).unwrap();
+    assert_eq!(
+        args.mode(),
+        Some(Mode::Beat {
+            palette: vec![[0, 0, 255]]
+        })
+    );
+}
+
+#[test]
+fn profile_sets_up_each_lamp() {
+    let config = Config::parse(
+        r#"
+[profiles.group]
+devices = ["192.168.1.21"]
+
+[[profiles.group.lamps]]
+device = "192.168.1.20"
+band = "bass"
+hue = 120
+max_brightness = 60
+
+[[profiles.group.lamps]]
+device = "aa:bb:cc:dd:ee:ff"
+band = "2000-8000"
+"#,
+    )
+    .unwrap();
+    let args = config
+        .profile(Some("group"))
+        .unwrap()
+        .unwrap()
+        .args()
+        .unwrap();
+    // lamps are connected to along with the plain devices
+    assert_eq!(
+        args.devices,
+        vec![
+            Target::Ip([192, 168, 1, 21].into()),
+            Target::Ip([192, 168, 1, 20].into()),
+            Target::Mac("AA:BB:CC:DD:EE:FF".to_string())
+        ]
+    );
+    let range = Band::FULL;
+    let first = args.lamp(&[192, 168, 1, 20].into(), "").unwrap();
+    let opts = first.member(range);
+    assert_eq!(opts.band, Band::BASS);
+    assert_eq!(opts.hue, 120.0);
+    assert_eq!(opts.maxb, 60);
+    let second = args
+        .lamp(&[10, 0, 0, 5].into(), "AA:BB:CC:DD:EE:FF")
+        .unwrap()
+        .member(range);
+    assert_eq!(
+        second.band,
+        Band {
+            min: 2000.0,
+            max: 8000.0
+        }
+    );
+    assert_eq!(second.maxb, 100);
+    assert!(args.lamp(&[192, 168, 1, 21].into(), "").is_none());
+
+    // devices on the command line still pick up the profile's lamp settings
+    let flags = Args::try_parse_from(["lamper", "-d", "192.168.1.20"]).unwrap();
+    let args = flags.or(args);
+    assert_eq!(args.devices.len(), 1);
+    assert_eq!(args.lamp(&[192, 168, 1, 20].into(), ""), Some(first));
+}
+
+#[test]
+fn bad_lamp_settings_are_rejected() {
+    let bad = |lamp: &str| {
+        let toml = format!("[[profiles.bad.lamps]]\n{}", lamp);
+        let config = Config::parse(&toml).unwrap();
+        config.profile(Some("bad")).unwrap().unwrap().args().err()
+    };
+    assert!(matches!(
+        bad("device = \"all\""),
+        Some(ConfigErr::ValueErr("device"))
+    ));
+    assert!(matches!(
+        bad("device = \"10.0.0.2\"\nhue = 360"),
+        Some(ConfigErr::ValueErr("hue"))
+    ));
+    assert!(matches!(
+        bad("device = \"10.0.0.2\"\nmax_brightness = 0"),
+        Some(ConfigErr::ValueErr("max_brightness"))
+    ));
+    assert!(matches!(
+        bad("device = \"10.0.0.2\"\nband = \"sub\""),
+        Some(ConfigErr::ValueErr("band"))
+    ));
+}
@@ -0,0 +1,115 @@
+mod mock;
+
+use std::{
+    net::Ipv4Addr,
+    sync::{mpsc, Arc, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use lamper::{
+    health::{self, HeartbeatOpts, Moved, Status},
+    udp::{self, DiscoveryOptions},
+};
+use mock::{Faults, MockLamp, MockOpts};
+
+fn opts(retries: Option<u32>) -> HeartbeatOpts {
+    HeartbeatOpts {
+        interval: Duration::from_millis(50),
+        backoff: Duration::from_millis(50),
+        max_backoff: Duration::from_millis(200),
+        retries,
+        discovery: DiscoveryOptions {
+            timeout: Duration::from_millis(300),
+            read_timeout: Duration::from_millis(200),
+            ..DiscoveryOptions::default()
+        },
+    }
+}
+
+// connect to the running mock and start checking it
+fn start(opts: HeartbeatOpts) -> (Arc<RwLock<Status>>, mpsc::Receiver<Moved>, JoinHandle<()>) {
+    let devices = udp::discover_with(&opts.discovery).unwrap();
+    let lamp = udp::connect_with(&devices[0], &opts.discovery).unwrap();
+    let status = Arc::new(RwLock::new(Status::Connected));
+    let (tx, rx) = mpsc::channel();
+    let hbstatus = Arc::clone(&status);
+    let mac = devices[0].mac.clone();
+    let hb = thread::spawn(move || health::heartbeat(vec![(lamp, mac)], hbstatus, tx, opts));
+    (status, rx, hb)
+}
+
+// wait for the status to match
+fn until(status: &RwLock<Status>, pred: impl Fn(Status) -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if pred(*status.read().unwrap()) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+fn stop(status: &RwLock<Status>, hb: JoinHandle<()>) {
+    *status.write().unwrap() = Status::Stopped;
+    hb.join().unwrap();
+}
+
+#[test]
+fn reconnects_when_the_lamp_answers_again() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let (status, _moves, hb) = start(opts(None));
+
+    mock.set_faults(Faults {
+        loss: 1.0,
+        ..Faults::default()
+    });
+    assert!(until(
+        &status,
+        |s| matches!(s, Status::Reconnecting { attempt } if attempt >= 2)
+    ));
+    mock.set_faults(Faults::default());
+    assert!(until(&status, |s| s == Status::Connected));
+    stop(&status, hb);
+}
+
+#[test]
+fn gives_up_after_the_retries() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let (status, _moves, hb) = start(opts(Some(2)));
+
+    mock.set_faults(Faults {
+        loss: 1.0,
+        ..Faults::default()
+    });
+    assert!(until(&status, |s| s == Status::Lost));
+    // the heartbeat is done once it gives up
+    hb.join().unwrap();
+}
+
+#[test]
+fn moved_lamp_is_found_by_mac() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let (status, moves, hb) = start(opts(None));
+
+    // same lamp, new address
+    drop(mock);
+    let moved = Ipv4Addr::new(127, 0, 0, 2);
+    let _mock = MockLamp::start(MockOpts {
+        ip: moved,
+        ..MockOpts::default()
+    });
+    assert_eq!(
+        moves.recv_timeout(Duration::from_secs(5)).unwrap(),
+        Moved {
+            index: 0,
+            ip: moved
+        }
+    );
+    assert!(until(&status, |s| s == Status::Connected));
+    stop(&status, hb);
+}
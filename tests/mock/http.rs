@@ -0,0 +1,289 @@
+// simulated govee developer api on a local port, with the api key check, a request
+// quota with the rate limit headers and devices that can go offline
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use lamper::udp::{State, Turn};
+use serde_json::{json, Value};
+
+pub const KEY: &str = "test-key";
+pub const DEVICE: &str = "AA:BB:CC:DD:EE:FF:00:11";
+pub const MODEL: &str = "H6159";
+
+#[derive(Debug, Clone)]
+pub struct ApiOpts {
+    pub state: State,
+    pub support_cmds: Vec<&'static str>,
+    // requests left before 429s, None for no limit
+    pub quota: Option<u32>,
+    // seconds from now the quota resets
+    pub reset: u64,
+}
+
+impl Default for ApiOpts {
+    fn default() -> Self {
+        ApiOpts {
+            state: State {
+                pwr: Turn::On,
+                bright: 40,
+                color: [0, 0, 255],
+                temp: 0,
+            },
+            support_cmds: vec!["turn", "brightness", "color", "colorTem"],
+            quota: None,
+            reset: 60,
+        }
+    }
+}
+
+// a request that got through the key check, method, path with query and body
+#[derive(Debug, Clone)]
+pub struct ApiRequest {
+    pub method: String,
+    pub path: String,
+    pub body: Value,
+}
+
+struct Shared {
+    opts: ApiOpts,
+    state: Mutex<State>,
+    online: AtomicBool,
+    quota: Mutex<Option<u32>>,
+    requests: Mutex<Vec<ApiRequest>>,
+    stop: AtomicBool,
+}
+
+pub struct MockApi {
+    pub url: String,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockApi {
+    pub fn start(opts: ApiOpts) -> MockApi {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let shared = Arc::new(Shared {
+            state: Mutex::new(opts.state.clone()),
+            online: AtomicBool::new(true),
+            quota: Mutex::new(opts.quota),
+            requests: Mutex::new(Vec::new()),
+            stop: AtomicBool::new(false),
+            opts,
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                while !shared.stop.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let _ = shared.serve(stream);
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(10)),
+                    }
+                }
+            })
+        };
+        MockApi {
+            url,
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.shared.state.lock().unwrap().clone()
+    }
+
+    pub fn set_state(&self, state: State) {
+        *self.shared.state.lock().unwrap() = state;
+    }
+
+    pub fn set_online(&self, online: bool) {
+        self.shared.online.store(online, Ordering::SeqCst);
+    }
+
+    pub fn requests(&self) -> Vec<ApiRequest> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockApi {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    fn serve(&self, stream: TcpStream) -> std::io::Result<()> {
+        stream.set_nonblocking(false)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut key = None;
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, val)) = line.split_once(':') {
+                match name.to_ascii_lowercase().as_str() {
+                    "govee-api-key" => key = Some(val.trim().to_string()),
+                    "content-length" => len = val.trim().parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+        let (status, headers, reply) = self.handle(&method, &path, key.as_deref(), body);
+        let reply = reply.to_string();
+        let mut res = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            status,
+            reply.len()
+        );
+        for (name, val) in headers {
+            res.push_str(&format!("{}: {}\r\n", name, val));
+        }
+        res.push_str("\r\n");
+        res.push_str(&reply);
+        let mut stream = stream;
+        stream.write_all(res.as_bytes())?;
+        stream.flush()
+    }
+
+    fn handle(
+        &self,
+        method: &str,
+        path: &str,
+        key: Option<&str>,
+        body: Value,
+    ) -> (&'static str, Vec<(&'static str, String)>, Value) {
+        if key != Some(KEY) {
+            return (
+                "401 Unauthorized",
+                Vec::new(),
+                json!({"code": 401, "message": "Invalid API Key"}),
+            );
+        }
+
+        let reset = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + self.opts.reset;
+        let mut headers = Vec::new();
+        if let Some(quota) = self.quota.lock().unwrap().as_mut() {
+            if *quota == 0 {
+                return (
+                    "429 Too Many Requests",
+                    vec![
+                        ("X-RateLimit-Remaining", "0".to_string()),
+                        ("X-RateLimit-Reset", reset.to_string()),
+                    ],
+                    json!({"code": 429, "message": "Too Many Requests"}),
+                );
+            }
+            *quota -= 1;
+            headers.push(("X-RateLimit-Remaining", quota.to_string()));
+            headers.push(("X-RateLimit-Reset", reset.to_string()));
+        }
+        self.requests.lock().unwrap().push(ApiRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.clone(),
+        });
+
+        let ok = |data: Value| json!({"code": 200, "message": "Success", "data": data});
+        let route = path.split('?').next().unwrap_or_default();
+        match (method, route) {
+            ("GET", "/v1/devices") => (
+                "200 OK",
+                headers,
+                ok(json!({"devices": [{
+                    "device": DEVICE,
+                    "model": MODEL,
+                    "deviceName": "Shelf strip",
+                    "controllable": true,
+                    "retrievable": true,
+                    "supportCmds": self.opts.support_cmds,
+                }]})),
+            ),
+            ("GET", "/v1/devices/state") => {
+                let state = self.state.lock().unwrap().clone();
+                let mode = if state.temp > 0 {
+                    json!({"colorTem": state.temp})
+                } else {
+                    json!({"color": {"r": state.color[0], "g": state.color[1], "b": state.color[2]}})
+                };
+                let pwr = if state.pwr == Turn::On { "on" } else { "off" };
+                (
+                    "200 OK",
+                    headers,
+                    ok(json!({
+                        "device": DEVICE,
+                        "model": MODEL,
+                        "properties": [
+                            {"online": self.online.load(Ordering::SeqCst)},
+                            {"powerState": pwr},
+                            {"brightness": state.bright},
+                            mode,
+                        ]
+                    })),
+                )
+            }
+            ("PUT", "/v1/devices/control") => {
+                let mut state = self.state.lock().unwrap();
+                let val = &body["cmd"]["value"];
+                match body["cmd"]["name"].as_str() {
+                    Some("turn") => {
+                        state.pwr = if val == "on" { Turn::On } else { Turn::Off };
+                    }
+                    Some("brightness") => state.bright = val.as_u64().unwrap_or(0) as u8,
+                    Some("color") => {
+                        let c = |k: &str| val[k].as_u64().unwrap_or(0) as u8;
+                        state.color = [c("r"), c("g"), c("b")];
+                        state.temp = 0;
+                    }
+                    Some("colorTem") => state.temp = val.as_u64().unwrap_or(0) as u16,
+                    _ => {
+                        return (
+                            "400 Bad Request",
+                            headers,
+                            json!({"code": 400, "message": "Unsupported Cmd Name"}),
+                        )
+                    }
+                }
+                ("200 OK", headers, ok(json!({})))
+            }
+            _ => (
+                "404 Not Found",
+                headers,
+                json!({"code": 404, "message": "Not Found"}),
+            ),
+        }
+    }
+}
@@ -0,0 +1,243 @@
+// simulated govee lamp for integration tests, answers scan on the multicast group and
+// devStatus and commands on 4003 like the real thing, with faults that can be injected
+#![allow(dead_code)]
+
+pub mod http;
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use lamper::{
+    protocol::{Message, Request, Response, Scan, Status},
+    udp::{State, Turn},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const MULTICAST: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SCAN_PORT: u16 = 4001;
+const REPLY_PORT: u16 = 4002;
+const CMD_PORT: u16 = 4003;
+// how often the listeners check whether to stop
+const POLL: Duration = Duration::from_millis(20);
+
+// only one mock can hold the ports, tests that use one take this first
+static PORTS: Mutex<()> = Mutex::new(());
+
+pub fn lock() -> MutexGuard<'static, ()> {
+    PORTS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// faults applied to everything the mock receives
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    // chance a packet is ignored, 0 to 1
+    pub loss: f32,
+    // packets to ignore before loss applies
+    pub drop_next: usize,
+    // delay before every reply
+    pub latency: Duration,
+    // chance a reply is replaced by garbage, 0 to 1
+    pub garbage: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockOpts {
+    // address reported in the scan reply, where devStatus and commands are expected
+    pub ip: Ipv4Addr,
+    pub device: String,
+    pub sku: String,
+    pub state: State,
+    pub faults: Faults,
+    pub seed: u64,
+}
+
+impl Default for MockOpts {
+    fn default() -> Self {
+        MockOpts {
+            ip: Ipv4Addr::LOCALHOST,
+            device: "1F:80:C5:32:32:36:72:4E".to_string(),
+            sku: "H6076".to_string(),
+            state: State {
+                pwr: Turn::On,
+                bright: 70,
+                color: [255, 120, 0],
+                temp: 0,
+            },
+            faults: Faults::default(),
+            seed: 0,
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    faults: Mutex<Faults>,
+    requests: Mutex<Vec<Request>>,
+    rng: Mutex<StdRng>,
+    stop: AtomicBool,
+}
+
+pub struct MockLamp {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl MockLamp {
+    pub fn start(opts: MockOpts) -> MockLamp {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(opts.state.clone()),
+            faults: Mutex::new(opts.faults.clone()),
+            requests: Mutex::new(Vec::new()),
+            rng: Mutex::new(StdRng::seed_from_u64(opts.seed)),
+            stop: AtomicBool::new(false),
+        });
+
+        let scan = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SCAN_PORT)).expect("bind 4001");
+        scan.join_multicast_v4(&MULTICAST, &Ipv4Addr::UNSPECIFIED)
+            .expect("join multicast");
+        scan.set_read_timeout(Some(POLL)).unwrap();
+        // bound to the lamp's own address so replies come from it
+        let cmd = UdpSocket::bind((opts.ip, CMD_PORT)).expect("bind 4003");
+        cmd.set_read_timeout(Some(POLL)).unwrap();
+
+        let reply = Response::Scan(Scan {
+            ip: opts.ip.to_string(),
+            device: opts.device.clone(),
+            sku: opts.sku.clone(),
+            ble_version_hard: "3.01.01".to_string(),
+            ble_version_soft: "1.03.01".to_string(),
+            wifi_version_hard: "1.00.10".to_string(),
+            wifi_version_soft: "1.02.03".to_string(),
+        });
+        let threads = vec![
+            {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || listen(&scan, &shared, |_| Some(reply.clone())))
+            },
+            {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    let inner = Arc::clone(&shared);
+                    listen(&cmd, &shared, move |req| inner.apply(req))
+                })
+            },
+        ];
+        MockLamp { shared, threads }
+    }
+
+    pub fn state(&self) -> State {
+        self.shared.state.lock().unwrap().clone()
+    }
+
+    pub fn set_state(&self, state: State) {
+        *self.shared.state.lock().unwrap() = state;
+    }
+
+    pub fn set_faults(&self, faults: Faults) {
+        *self.shared.faults.lock().unwrap() = faults;
+    }
+
+    // every well formed request that got through, oldest first
+    pub fn requests(&self) -> Vec<Request> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+
+    pub fn clear_requests(&self) {
+        self.shared.requests.lock().unwrap().clear();
+    }
+}
+
+impl Drop for MockLamp {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    // update the state from a command, devStatus gets the state back
+    fn apply(&self, req: Request) -> Option<Response> {
+        let mut state = self.state.lock().unwrap();
+        match req {
+            Request::DevStatus {} => {
+                return Some(Response::DevStatus(Status {
+                    on_off: (state.pwr == Turn::On) as u8,
+                    brightness: state.bright,
+                    color: state.color.into(),
+                    temp: state.temp,
+                }))
+            }
+            Request::Turn { value } => {
+                state.pwr = if value == 0 { Turn::Off } else { Turn::On };
+            }
+            Request::Brightness { value } => state.bright = value.min(100),
+            Request::Colorwc { color, temp } => {
+                state.color = color.into();
+                state.temp = temp.unwrap_or(0);
+            }
+            Request::Scan { .. } => {}
+        }
+        None
+    }
+
+    // whether the next packet gets through
+    fn deliver(&self) -> bool {
+        let mut faults = self.faults.lock().unwrap();
+        if faults.drop_next > 0 {
+            faults.drop_next -= 1;
+            return false;
+        }
+        self.rng.lock().unwrap().gen::<f32>() >= faults.loss
+    }
+}
+
+// read requests until stopped, replies go to port 4002 of the sender like a real lamp
+fn listen(socket: &UdpSocket, shared: &Shared, handle: impl Fn(Request) -> Option<Response>) {
+    let mut buf = [0u8; 1024];
+    while !shared.stop.load(Ordering::SeqCst) {
+        let (len, src) = match socket.recv_from(&mut buf) {
+            Ok(val) => val,
+            Err(_) => continue,
+        };
+        if !shared.deliver() {
+            continue;
+        }
+        let req = match serde_json::from_slice::<Message<Request>>(&buf[..len]) {
+            Ok(msg) => msg.msg,
+            Err(_) => continue,
+        };
+        shared.requests.lock().unwrap().push(req.clone());
+        let res = match handle(req) {
+            Some(res) => res,
+            None => continue,
+        };
+
+        let faults = shared.faults.lock().unwrap().clone();
+        thread::sleep(faults.latency);
+        let out = if shared.rng.lock().unwrap().gen::<f32>() < faults.garbage {
+            garbage(&mut shared.rng.lock().unwrap())
+        } else {
+            res.to_vec().unwrap()
+        };
+        let dst = SocketAddr::new(src.ip(), REPLY_PORT);
+        let _ = socket.send_to(&out, dst);
+    }
+}
+
+// truncated json or random bytes
+fn garbage(rng: &mut StdRng) -> Vec<u8> {
+    if rng.gen() {
+        br#"{"msg":{"cmd":"devStatus","data":{"onOff":1,"bri"#.to_vec()
+    } else {
+        (0..rng.gen_range(1..64)).map(|_| rng.gen()).collect()
+    }
+}
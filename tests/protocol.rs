@@ -0,0 +1,129 @@
+use lamper::{
+    protocol::{Request, Response, Rgb, Status},
+    udp::{CmdErr, State, Turn},
+};
+use proptest::prelude::*;
+
+fn status(on_off: u8, brightness: u8, color: [u8; 3], temp: u16) -> Vec<u8> {
+    Response::DevStatus(Status {
+        on_off,
+        brightness,
+        color: color.into(),
+        temp,
+    })
+    .to_vec()
+    .unwrap()
+}
+
+// a devStatus reply as the lamp sends it
+const REPLY: &str = r#"{"msg":{"cmd":"devStatus","data":{"onOff":0,"brightness":42,"color":{"r":1,"g":2,"b":3},"colorTemInKelvin":0}}}"#;
+
+#[test]
+fn powered_off_lamp_parses() {
+    let state = match Response::parse(REPLY.as_bytes()).unwrap() {
+        Response::DevStatus(status) => State::try_from(status).unwrap(),
+        res => panic!("expected devStatus, got {:?}", res),
+    };
+    assert_eq!(
+        state,
+        State {
+            pwr: Turn::Off,
+            bright: 42,
+            color: [1, 2, 3],
+            temp: 0
+        }
+    );
+}
+
+#[test]
+fn requests_match_the_lan_api() {
+    let json = |req: Request| String::from_utf8(req.to_vec().unwrap()).unwrap();
+    assert_eq!(
+        json(Request::DevStatus {}),
+        r#"{"msg":{"cmd":"devStatus","data":{}}}"#
+    );
+    assert_eq!(
+        json(Request::Turn { value: 1 }),
+        r#"{"msg":{"cmd":"turn","data":{"value":1}}}"#
+    );
+    assert_eq!(
+        json(Request::Colorwc {
+            color: Rgb { r: 1, g: 2, b: 3 },
+            temp: None
+        }),
+        r#"{"msg":{"cmd":"colorwc","data":{"color":{"r":1,"g":2,"b":3}}}}"#
+    );
+    assert_eq!(
+        json(Request::Colorwc {
+            color: Rgb { r: 0, g: 0, b: 0 },
+            temp: Some(4000)
+        }),
+        r#"{"msg":{"cmd":"colorwc","data":{"color":{"r":0,"g":0,"b":0},"colorTemInKelvin":4000}}}"#
+    );
+}
+
+#[test]
+fn out_of_range_numbers_are_not_truncated() {
+    let reply = REPLY.replace(r#""brightness":42"#, r#""brightness":298"#);
+    assert!(Response::parse(reply.as_bytes()).is_err());
+    let reply = REPLY.replace(r#""brightness":42"#, r#""brightness":-1"#);
+    assert!(Response::parse(reply.as_bytes()).is_err());
+}
+
+proptest! {
+    #[test]
+    fn any_bytes_never_panic(buf in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let _ = Response::parse(&buf);
+    }
+
+    #[test]
+    fn truncated_replies_are_errors(cut in 0..REPLY.len()) {
+        prop_assert!(Response::parse(&REPLY.as_bytes()[..cut]).is_err());
+    }
+
+    #[test]
+    fn mangled_replies_never_panic(i in 0..REPLY.len(), byte in any::<u8>()) {
+        let mut buf = REPLY.as_bytes().to_vec();
+        buf[i] = byte;
+        if let Ok(Response::DevStatus(status)) = Response::parse(&buf) {
+            let _ = State::try_from(status);
+        }
+    }
+
+    #[test]
+    fn status_round_trips(
+        on_off in 0u8..=1,
+        brightness in 0u8..=100,
+        color in any::<[u8; 3]>(),
+        temp in any::<u16>(),
+    ) {
+        let state = match Response::parse(&status(on_off, brightness, color, temp)).unwrap() {
+            Response::DevStatus(status) => State::try_from(status).unwrap(),
+            res => panic!("expected devStatus, got {:?}", res),
+        };
+        prop_assert_eq!(state.pwr, if on_off == 1 { Turn::On } else { Turn::Off });
+        prop_assert_eq!(state.bright, brightness);
+        prop_assert_eq!(state.color, color);
+        prop_assert_eq!(state.temp, temp);
+    }
+
+    #[test]
+    fn invalid_fields_are_named(on_off in 2u8.., brightness in 101u8..) {
+        let parse = |buf: Vec<u8>| match Response::parse(&buf).unwrap() {
+            Response::DevStatus(status) => State::try_from(status),
+            res => panic!("expected devStatus, got {:?}", res),
+        };
+        let on_off = parse(status(on_off, 50, [0, 0, 0], 0));
+        prop_assert!(
+            matches!(on_off, Err(CmdErr::InvalidFieldErr { field: "onOff", .. })),
+            "{:?}",
+            on_off
+        );
+        let brightness = parse(status(1, brightness, [0, 0, 0], 0));
+        prop_assert!(
+            matches!(brightness, Err(CmdErr::InvalidFieldErr { field: "brightness", .. })),
+            "{:?}",
+            brightness
+        );
+    }
+}
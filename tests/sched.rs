@@ -0,0 +1,204 @@
+mod mock;
+
+use std::{net::Ipv4Addr, thread, time::Duration};
+
+use lamper::{
+    bright::BrightMap,
+    colproc::Update,
+    group::{LampGroup, MemberOpts},
+    protocol::Request,
+    sched::{Metrics, SchedOpts, Scheduler},
+    udp::Lamp,
+};
+use mock::{MockLamp, MockOpts};
+
+// time for a command to reach the mock
+const SETTLE: Duration = Duration::from_millis(100);
+
+// the mock has to be running
+fn scheduler(opts: SchedOpts) -> Scheduler {
+    scheduler_with(opts, MemberOpts::default(), BrightMap::default())
+}
+
+// one lamp with its own options under the group's brightness mapping
+fn scheduler_with(opts: SchedOpts, member: MemberOpts, bright: BrightMap) -> Scheduler {
+    let mut group = LampGroup::new();
+    group.add(Lamp::connect(Ipv4Addr::LOCALHOST).unwrap(), member);
+    group.set_bright(bright);
+    Scheduler::new(group, opts)
+}
+
+fn sent(mock: &MockLamp) -> usize {
+    mock.requests()
+        .iter()
+        .filter(|req| !matches!(req, Request::DevStatus {}))
+        .count()
+}
+
+#[test]
+fn newest_values_win_and_unchanged_ones_are_skipped() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    // a burst for the first frame then one command every 200 ms
+    let mut sched = scheduler(SchedOpts {
+        rate: 5.0,
+        burst: 2.0,
+    });
+
+    sched.push(vec![Update::Both(50, [1, 2, 3])]);
+    sched.flush().unwrap();
+    assert_eq!(sched.wait(), None);
+
+    // out of tokens, these wait and only the newest brightness survives
+    sched.push(vec![Update::Brightness(60)]);
+    sched.push(vec![Update::Both(70, [1, 2, 3])]);
+    sched.flush().unwrap();
+    assert!(sched.wait().unwrap() > Duration::ZERO);
+
+    thread::sleep(sched.wait().unwrap() + Duration::from_millis(5));
+    sched.flush().unwrap();
+    sched.push(vec![Update::Both(70, [1, 2, 3])]);
+    assert_eq!(sched.wait(), None);
+    thread::sleep(SETTLE);
+
+    assert_eq!(mock.state().bright, 70);
+    assert_eq!(mock.state().color, [1, 2, 3]);
+    assert_eq!(sent(&mock), 3);
+    assert_eq!(
+        sched.metrics(),
+        Metrics {
+            frames: 4,
+            merged: 1,
+            dropped: 1,
+            skipped: 3,
+            sent: 3
+        }
+    );
+}
+
+#[test]
+fn rate_limits_commands() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let mut sched = scheduler(SchedOpts::from_delay(Duration::from_millis(50)));
+
+    // a new value every 10 ms for half a second
+    for level in 1..=50 {
+        sched.push(vec![Update::Brightness(level)]);
+        sched.flush().unwrap();
+        thread::sleep(Duration::from_millis(10));
+    }
+    thread::sleep(sched.wait().unwrap_or_default());
+    sched.flush().unwrap();
+    thread::sleep(SETTLE);
+
+    // the burst plus one every 50 ms, the last value still gets through
+    let count = sent(&mock);
+    assert!((8..=14).contains(&count), "{} commands sent", count);
+    assert_eq!(mock.state().bright, 50);
+    assert_eq!(sched.metrics().sent as usize, count);
+}
+
+#[test]
+fn forgotten_values_are_sent_again() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let mut sched = scheduler(SchedOpts::from_delay(Duration::ZERO));
+
+    sched.push(vec![Update::Color([9, 9, 9])]);
+    sched.flush().unwrap();
+    sched.push(vec![Update::Color([9, 9, 9])]);
+    sched.flush().unwrap();
+    sched.forget();
+    sched.push(vec![Update::Color([9, 9, 9])]);
+    sched.flush().unwrap();
+    thread::sleep(SETTLE);
+
+    assert_eq!(sent(&mock), 2);
+    assert_eq!(sched.metrics().skipped, 1);
+}
+
+#[test]
+fn color_is_sent_while_brightness_keeps_changing() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    // one command at a time every 20 ms, a new level and color every 5 ms
+    let mut sched = scheduler(SchedOpts {
+        rate: 50.0,
+        burst: 1.0,
+    });
+    for i in 0..100u8 {
+        sched.push(vec![Update::Both(i % 100 + 1, [i, 0, 255 - i])]);
+        sched.flush().unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+    thread::sleep(SETTLE);
+
+    let requests = mock.requests();
+    let count = |color: bool| {
+        requests
+            .iter()
+            .filter(|req| match req {
+                Request::Colorwc { .. } => color,
+                Request::Brightness { .. } => !color,
+                _ => false,
+            })
+            .count()
+    };
+    // the two kinds take turns for the tokens
+    let (bright, color) = (count(false), count(true));
+    assert!(color >= 8, "{} colors and {} levels sent", color, bright);
+    assert!(bright.abs_diff(color) <= 1);
+}
+
+#[test]
+fn brightness_is_mapped_before_sending() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let bright = BrightMap {
+        min: 20,
+        max: 60,
+        floor: 10,
+        ..BrightMap::default()
+    };
+    let mut sched = scheduler_with(
+        SchedOpts::from_delay(Duration::ZERO),
+        MemberOpts::default(),
+        bright,
+    );
+
+    sched.push(vec![Update::Brightness(0)]);
+    sched.flush().unwrap();
+    thread::sleep(SETTLE);
+    assert_eq!(mock.state().bright, 10);
+    sched.push(vec![Update::Brightness(100)]);
+    sched.flush().unwrap();
+    thread::sleep(SETTLE);
+    assert_eq!(mock.state().bright, 60);
+}
+
+#[test]
+fn mapping_keeps_member_ceilings() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let member = MemberOpts {
+        maxb: 40,
+        ..MemberOpts::default()
+    };
+    let bright = BrightMap {
+        max: 60,
+        floor: 50,
+        ..BrightMap::default()
+    };
+    let mut sched = scheduler_with(SchedOpts::from_delay(Duration::ZERO), member, bright);
+
+    // the group's 60 is capped at the lamp's own 40, and the floor with it
+    let members = sched.group().members();
+    assert_eq!(members[0].opts.maxb, 40);
+    assert_eq!(members[0].brightness(100), 40);
+    assert_eq!(members[0].brightness(0), 40);
+    sched.push(vec![Update::Brightness(100)]);
+    sched.flush().unwrap();
+    thread::sleep(SETTLE);
+    assert_eq!(mock.state().bright, 40);
+}
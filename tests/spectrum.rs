@@ -0,0 +1,163 @@
+use std::f32::consts::TAU;
+
+use lamper::spectrum::{BandAnalyzer, Hue, Scale, Spectrum, Window};
+
+const RATE: u32 = 44100;
+
+const LEN: usize = 4096;
+
+// full scale sine landing exactly on a bin
+fn sine(bin: usize) -> Vec<f32> {
+    (0..LEN)
+        .map(|i| (TAU * bin as f32 * i as f32 / LEN as f32).sin())
+        .collect()
+}
+
+fn peak(spectrum: &[f32]) -> (usize, f32) {
+    spectrum
+        .iter()
+        .copied()
+        .enumerate()
+        .fold(
+            (0, f32::MIN),
+            |best, (i, val)| if val > best.1 { (i, val) } else { best },
+        )
+}
+
+#[test]
+fn full_scale_sine_reads_zero_db_in_its_bin() {
+    let mut spectrum = Spectrum::new(LEN, Window::Hann);
+    assert_eq!(spectrum.len(), LEN);
+    for bin in [10, 93, 500, 1800] {
+        let (index, db) = peak(spectrum.db(&sine(bin)));
+        assert_eq!(index, bin);
+        assert!(db.abs() < 0.1, "bin {} read {} dB", bin, db);
+    }
+    // half scale is 6 dB down
+    let half: Vec<f32> = sine(93).iter().map(|x| x / 2.0).collect();
+    let db = spectrum.db(&half)[93];
+    assert!((db + 6.02).abs() < 0.1, "half scale read {} dB", db);
+}
+
+#[test]
+fn magnitude_is_comparable_across_windows() {
+    let data = sine(200);
+    let mut rect = Spectrum::new(LEN, Window::Rectangular);
+    let (index, reference) = peak(rect.magnitude(&data));
+    assert_eq!(index, 200);
+    for window in [Window::Hann, Window::Hamming, Window::BlackmanHarris] {
+        let mut spectrum = Spectrum::new(LEN, window);
+        let (index, val) = peak(spectrum.magnitude(&data));
+        assert_eq!(index, 200);
+        assert!(
+            (val / reference - 1.0).abs() < 0.01,
+            "{:?} peak {} against {}",
+            window,
+            val,
+            reference
+        );
+    }
+}
+
+#[test]
+fn short_frames_are_zero_padded() {
+    let mut spectrum = Spectrum::new(LEN, Window::Hann);
+    let silent = spectrum.magnitude(&[]).to_vec();
+    assert_eq!(silent.len(), LEN / 2);
+    assert!(silent.iter().all(|val| *val == 0.0));
+    let (index, _) = peak(spectrum.magnitude(&sine(300)[..LEN / 2]));
+    assert!((299..=301).contains(&index));
+}
+
+fn analyzer(scale: Scale) -> BandAnalyzer {
+    BandAnalyzer::new(scale, 20.0, 20000.0, LEN / 2, RATE)
+}
+
+#[test]
+fn band_edges_span_the_range() {
+    let close = |a: f32, b: f32| (a - b).abs() / b < 1e-3;
+    for scale in [Scale::Log(10), Scale::Octave(3), Scale::Mel(24)] {
+        let bands = analyzer(scale);
+        let edges = bands.edges();
+        assert!(close(edges[0], 20.0) && close(*edges.last().unwrap(), 20000.0));
+        assert!(edges.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    // log bands share a ratio, a thousandfold range in third octaves is 30 bands
+    let log = analyzer(Scale::Log(10));
+    assert_eq!(log.edges().len(), 11);
+    assert!(log
+        .edges()
+        .windows(2)
+        .all(|pair| close(pair[1] / pair[0], 1000f32.powf(0.1))));
+    let octave = analyzer(Scale::Octave(3));
+    assert_eq!(octave.edges().len(), 31);
+    let third = 2f32.powf(1.0 / 3.0);
+    assert!(octave
+        .edges()
+        .windows(2)
+        .all(|pair| (pair[1] / pair[0] - third).abs() < 0.01));
+
+    // mel bands are evenly spaced in mel, so they widen with frequency
+    let mel = analyzer(Scale::Mel(24));
+    assert_eq!(mel.edges().len(), 25);
+    let widths: Vec<f32> = mel
+        .edges()
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .collect();
+    assert!(widths.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn low_and_high_sines_land_low_and_high() {
+    let mut spectrum = Spectrum::new(LEN, Window::Hann);
+    // bins 10 and 900, about 108hz and 9.7khz
+    let low = spectrum.magnitude(&sine(10)).to_vec();
+    let high = spectrum.magnitude(&sine(900)).to_vec();
+    for scale in [Scale::Log(16), Scale::Octave(3), Scale::Mel(32)] {
+        let mut bands = analyzer(scale);
+        for hue in [Hue::Centroid, Hue::Dominant] {
+            bands.analyze(&low);
+            let lo = bands.position(hue);
+            bands.analyze(&high);
+            let hi = bands.position(hue);
+            assert!(
+                lo < 0.4 && hi > 0.6,
+                "{:?} {:?} gave {} and {}",
+                scale,
+                hue,
+                lo,
+                hi
+            );
+        }
+    }
+
+    // the dominant band is the one the sine's frequency falls in
+    let mut bands = analyzer(Scale::Log(10));
+    bands.analyze(&high);
+    let hz = 900.0 * RATE as f32 / LEN as f32;
+    let band = bands
+        .edges()
+        .windows(2)
+        .position(|pair| hz <= pair[1])
+        .unwrap();
+    let expected = (band as f32 + 0.5) / 10.0;
+    assert!((bands.position(Hue::Dominant) - expected).abs() < 1e-6);
+}
+
+#[test]
+fn level_follows_amplitude() {
+    let mut spectrum = Spectrum::new(LEN, Window::Hann);
+    let mut bands = analyzer(Scale::Octave(3));
+    bands.analyze(spectrum.magnitude(&sine(93)));
+    let full = bands.level();
+    let quarter: Vec<f32> = sine(93).iter().map(|x| x / 4.0).collect();
+    bands.analyze(spectrum.magnitude(&quarter));
+    assert!((full - bands.level() - 12.04).abs() < 0.1);
+    // a full scale sine sits near 0 dB
+    assert!(full.abs() < 3.0, "full scale level {}", full);
+    // silence has no position
+    bands.analyze(spectrum.magnitude(&[]));
+    assert_eq!(bands.position(Hue::Centroid), 0.0);
+}
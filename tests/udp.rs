@@ -0,0 +1,286 @@
+mod mock;
+
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use lamper::{
+    protocol::Request,
+    udp::{self, Cmd, CmdErr, DiscoveryOptions, InitErr, Lamp, State, Turn},
+};
+use mock::{Faults, MockLamp, MockOpts};
+
+const SCAN: Duration = Duration::from_millis(300);
+// time for a command to reach the mock
+const SETTLE: Duration = Duration::from_millis(100);
+
+// the mock has to be running
+fn connect() -> Lamp {
+    let devices = udp::discover(SCAN).unwrap();
+    assert_eq!(devices.len(), 1);
+    udp::connect(&devices[0]).unwrap()
+}
+
+#[test]
+fn init_finds_the_lamp() {
+    let _ports = mock::lock();
+    let opts = MockOpts::default();
+    let mock = MockLamp::start(opts.clone());
+
+    let lamp = udp::init().unwrap();
+    assert_eq!(*lamp.addr.ip(), Ipv4Addr::LOCALHOST);
+    assert_eq!(lamp.init, opts.state);
+    drop(mock);
+}
+
+#[test]
+fn discover_reads_the_scan_reply() {
+    let _ports = mock::lock();
+    let _mock = MockLamp::start(MockOpts::default());
+
+    let devices = udp::discover(SCAN).unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].sku, "H6076");
+    assert_eq!(devices[0].mac, "C5:32:32:36:72:4E");
+    assert_eq!(devices[0].firmware.wifi_soft, "1.02.03");
+}
+
+#[test]
+fn init_without_replies_is_not_found() {
+    let _ports = mock::lock();
+    let _mock = MockLamp::start(MockOpts {
+        faults: Faults {
+            garbage: 1.0,
+            ..Faults::default()
+        },
+        ..MockOpts::default()
+    });
+
+    assert!(matches!(udp::init(), Err(InitErr::NotFoundErr)));
+}
+
+#[test]
+fn powered_off_lamp_connects() {
+    let _ports = mock::lock();
+    let mut opts = MockOpts::default();
+    opts.state.pwr = Turn::Off;
+    let _mock = MockLamp::start(opts);
+
+    let lamp = connect();
+    assert_eq!(lamp.init.pwr, Turn::Off);
+}
+
+#[test]
+fn send_cmd_changes_state() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let lamp = connect();
+
+    lamp.send_cmd(Cmd::OnOff(Turn::Off)).unwrap();
+    lamp.send_cmd(Cmd::Brightness(30)).unwrap();
+    lamp.send_cmd(Cmd::Color([1, 2, 3])).unwrap();
+    thread::sleep(SETTLE);
+    assert_eq!(
+        mock.state(),
+        State {
+            pwr: Turn::Off,
+            bright: 30,
+            color: [1, 2, 3],
+            temp: 0
+        }
+    );
+
+    lamp.send_cmd(Cmd::Temp(4000)).unwrap();
+    thread::sleep(SETTLE);
+    assert_eq!(mock.state().temp, 4000);
+
+    assert!(matches!(
+        lamp.send_cmd(Cmd::Brightness(101)),
+        Err(CmdErr::InvalidBrightnessErr(101))
+    ));
+}
+
+#[test]
+fn check_survives_latency() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let lamp = connect();
+
+    mock.set_faults(Faults {
+        latency: Duration::from_millis(200),
+        ..Faults::default()
+    });
+    assert!(lamp.check().is_ok());
+}
+
+#[test]
+fn check_fails_without_replies() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let lamp = connect();
+    assert!(lamp.check().is_ok());
+
+    mock.set_faults(Faults {
+        loss: 1.0,
+        ..Faults::default()
+    });
+    assert!(matches!(lamp.check(), Err(CmdErr::RecvErr(_))));
+
+    mock.set_faults(Faults {
+        garbage: 1.0,
+        ..Faults::default()
+    });
+    assert!(matches!(lamp.check(), Err(CmdErr::SerdeErr(_))));
+}
+
+#[test]
+fn restore_puts_everything_back() {
+    let _ports = mock::lock();
+    let opts = MockOpts::default();
+    let mock = MockLamp::start(opts.clone());
+    let lamp = connect();
+
+    lamp.send_cmd(Cmd::Brightness(5)).unwrap();
+    lamp.send_cmd(Cmd::Temp(6500)).unwrap();
+    thread::sleep(SETTLE);
+    assert_ne!(mock.state(), opts.state);
+
+    lamp.restore().unwrap();
+    assert_eq!(mock.state(), opts.state);
+}
+
+#[test]
+fn restore_white_and_off() {
+    let _ports = mock::lock();
+    let state = State {
+        pwr: Turn::Off,
+        bright: 40,
+        color: [0, 0, 0],
+        temp: 2700,
+    };
+    let mock = MockLamp::start(MockOpts {
+        state: state.clone(),
+        ..MockOpts::default()
+    });
+    let lamp = connect();
+
+    lamp.send_cmd(Cmd::OnOff(Turn::On)).unwrap();
+    lamp.send_cmd(Cmd::Color([0, 255, 0])).unwrap();
+    thread::sleep(SETTLE);
+    mock.clear_requests();
+
+    lamp.restore().unwrap();
+    assert_eq!(mock.state(), state);
+    // power off goes last so nothing after it can turn the lamp back on
+    let turns: Vec<usize> = mock
+        .requests()
+        .iter()
+        .enumerate()
+        .filter(|(_, req)| matches!(req, Request::Turn { .. }))
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(turns, vec![2]);
+}
+
+#[test]
+fn restore_retries_lost_commands() {
+    let _ports = mock::lock();
+    let opts = MockOpts::default();
+    let mock = MockLamp::start(opts.clone());
+    let lamp = connect();
+
+    lamp.send_cmd(Cmd::Brightness(5)).unwrap();
+    thread::sleep(SETTLE);
+    // power and brightness of the first attempt never arrive
+    mock.set_faults(Faults {
+        drop_next: 2,
+        ..Faults::default()
+    });
+    lamp.restore().unwrap();
+    assert_eq!(mock.state(), opts.state);
+}
+
+#[test]
+fn restore_gives_up() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let lamp = connect();
+
+    mock.set_faults(Faults {
+        loss: 1.0,
+        ..Faults::default()
+    });
+    assert!(matches!(lamp.restore(), Err(CmdErr::RecvErr(_))));
+}
+
+#[test]
+fn taken_port_is_an_error() {
+    let _ports = mock::lock();
+    let _taken = UdpSocket::bind("0.0.0.0:4002").unwrap();
+
+    assert!(matches!(
+        udp::discover(SCAN),
+        Err(InitErr::BindErr {
+            kind: ErrorKind::AddrInUse,
+            ..
+        })
+    ));
+    assert!(matches!(udp::init(), Err(InitErr::BindErr { .. })));
+}
+
+#[test]
+fn discovery_retries_then_gives_up() {
+    let _ports = mock::lock();
+    let _mock = MockLamp::start(MockOpts::default());
+    // nothing listens on this port
+    let opts = DiscoveryOptions {
+        scan_port: 4010,
+        timeout: Duration::from_millis(100),
+        retries: 2,
+        ..DiscoveryOptions::default()
+    };
+
+    let start = Instant::now();
+    assert!(udp::discover_with(&opts).unwrap().is_empty());
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert!(matches!(udp::init_with(&opts), Err(InitErr::NotFoundErr)));
+}
+
+#[test]
+fn status_timeout_is_configurable() {
+    let _ports = mock::lock();
+    let mock = MockLamp::start(MockOpts::default());
+    let opts = DiscoveryOptions {
+        timeout: SCAN,
+        read_timeout: Duration::from_millis(50),
+        ..DiscoveryOptions::default()
+    };
+    mock.set_faults(Faults {
+        latency: Duration::from_millis(200),
+        ..Faults::default()
+    });
+
+    // the scan reply is late too but still lands inside the scan window
+    let devices = udp::discover_with(&opts).unwrap();
+    assert!(matches!(
+        udp::connect_with(&devices[0], &opts),
+        Err(InitErr::DevStatusErr(CmdErr::RecvErr(_)))
+    ));
+}
+
+#[test]
+fn connect_by_ip_skips_the_scan() {
+    let _ports = mock::lock();
+    let opts = MockOpts::default();
+    let mock = MockLamp::start(opts.clone());
+
+    let lamp = Lamp::connect(Ipv4Addr::LOCALHOST).unwrap();
+    assert_eq!(lamp.init, opts.state);
+    assert!(!mock
+        .requests()
+        .iter()
+        .any(|req| matches!(req, Request::Scan { .. })));
+}